@@ -0,0 +1,324 @@
+use clap::{Parser, Subcommand};
+use std::{
+    error::Error,
+    fs,
+    io::{self, BufRead, Read, Write},
+    iter,
+    path::PathBuf,
+    process,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Join an ipmb bus to send, receive and inspect messages.
+#[derive(Parser)]
+#[clap(name = "ipmb", version)]
+struct Cli {
+    /// Identifier of the bus
+    #[clap(short, long)]
+    identifier: String,
+    /// Label element of this endpoint, can be repeated
+    #[clap(short, long = "label")]
+    labels: Vec<String>,
+    /// Token shared by the endpoints of the bus
+    #[clap(short, long, default_value = "")]
+    token: String,
+    /// Join timeout in milliseconds
+    #[clap(long)]
+    join_timeout: Option<u64>,
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Send a BytesMessage read from each file, or from stdin when there is none
+    Send {
+        /// Label op of the selector, e.g. `planet & !mars`
+        #[clap(value_parser)]
+        label_op: ipmb::LabelOp,
+        files: Vec<PathBuf>,
+        /// Deliver to every matching endpoint instead of one
+        #[clap(short, long)]
+        multicast: bool,
+        /// How long the bus controller keeps the message when no endpoint matches, in milliseconds
+        #[clap(long, default_value_t = 0)]
+        ttl: u64,
+        /// Format of the BytesMessage
+        #[clap(short, long, default_value_t = 0)]
+        format: u16,
+        /// Send every line of stdin as a message
+        #[clap(long)]
+        lines: bool,
+    },
+    /// Print the BytesMessages sent to this endpoint
+    Recv {
+        /// Print the data as hex instead of UTF-8
+        #[clap(long)]
+        hex: bool,
+        /// Exit after this many messages
+        #[clap(short = 'n', long)]
+        count: Option<usize>,
+        /// Exit when no message arrives for this many milliseconds
+        #[clap(long)]
+        timeout: Option<u64>,
+        /// Reply to requests with the same message, see `ping`
+        #[clap(long)]
+        echo: bool,
+    },
+    /// List the endpoints on the bus
+    Endpoints {
+        /// Timeout in milliseconds
+        #[clap(long, default_value_t = 1000)]
+        timeout: u64,
+    },
+    /// Measure the round-trip latency to an endpoint running `recv --echo`
+    Ping {
+        /// Label op of the selector
+        #[clap(value_parser)]
+        label_op: ipmb::LabelOp,
+        /// Number of requests
+        #[clap(short = 'n', long, default_value_t = 5)]
+        count: usize,
+        /// Size of the payload in bytes
+        #[clap(short, long, default_value_t = 64)]
+        size: usize,
+        /// Milliseconds between requests
+        #[clap(long, default_value_t = 1000)]
+        interval: u64,
+        /// Milliseconds to wait for each reply
+        #[clap(long, default_value_t = 1000)]
+        timeout: u64,
+    },
+}
+
+fn main() {
+    let mut builder = env_logger::Builder::new();
+    builder.filter_level(log::LevelFilter::Warn);
+    builder.parse_default_env();
+    builder.init();
+
+    if let Err(err) = run(Cli::parse()) {
+        eprintln!("ipmb: {err}");
+        process::exit(1);
+    }
+}
+
+fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
+    let options = ipmb::Options::new(&cli.identifier, ipmb::Label::from(&cli.labels), &cli.token);
+    let (sender, mut receiver) = ipmb::join::<ipmb::BytesMessage, ipmb::BytesMessage>(
+        options,
+        cli.join_timeout.map(Duration::from_millis),
+    )?;
+
+    match cli.command {
+        Command::Send {
+            label_op,
+            files,
+            multicast,
+            ttl,
+            format,
+            lines,
+        } => {
+            let mut payloads = Vec::new();
+            if files.is_empty() {
+                if lines {
+                    for line in io::stdin().lock().lines() {
+                        payloads.push(line?.into_bytes());
+                    }
+                } else {
+                    let mut data = Vec::new();
+                    io::stdin().read_to_end(&mut data)?;
+                    payloads.push(data);
+                }
+            } else {
+                for file in &files {
+                    payloads.push(fs::read(file)?);
+                }
+            }
+
+            for data in payloads {
+                let mut selector = if multicast {
+                    ipmb::Selector::multicast(label_op.clone())
+                } else {
+                    ipmb::Selector::unicast(label_op.clone())
+                };
+                selector.ttl = Duration::from_millis(ttl);
+
+                // Confirmed, so the message has reached the bus controller when exiting
+                let delivery = sender.send_confirmed(
+                    ipmb::Message::new(selector, ipmb::BytesMessage { format, data }),
+                    Some(Duration::from_secs(5)),
+                )?;
+                match delivery {
+                    ipmb::Delivery::Routed { receivers } => {
+                        eprintln!("routed to {receivers} endpoint(s)")
+                    }
+                    ipmb::Delivery::Buffered { ttl } => {
+                        eprintln!("buffered for {}ms", ttl.as_millis());
+                        // The buffer lives in the bus controller, it's gone when this process exits
+                        let info = sender.bus_info(Some(Duration::from_secs(5)))?;
+                        if info.controller.id == sender.endpoint_id() {
+                            return Err("this process is the bus controller, the message is lost when it exits".into());
+                        }
+                    }
+                    ipmb::Delivery::NoRoute => eprintln!("no route"),
+                }
+            }
+        }
+        Command::Recv {
+            hex,
+            count,
+            timeout,
+            echo,
+        } => {
+            let mut stdout = io::stdout().lock();
+            let mut received = 0;
+
+            while count.map_or(true, |count| received < count) {
+                let msg = match receiver.recv(timeout.map(Duration::from_millis)) {
+                    Ok(msg) => msg,
+                    Err(ipmb::RecvError::Timeout) => break,
+                    Err(err) => return Err(err.into()),
+                };
+                received += 1;
+
+                let source = msg
+                    .source()
+                    .map(|id| id.to_string())
+                    .unwrap_or_else(|| "?".to_string());
+                writeln!(
+                    stdout,
+                    "{} format {}, {} bytes{}",
+                    source,
+                    msg.payload.format,
+                    msg.payload.data.len(),
+                    if msg.is_request() { ", request" } else { "" },
+                )?;
+                if hex {
+                    write_hex(&mut stdout, &msg.payload.data)?;
+                } else {
+                    writeln!(stdout, "{}", String::from_utf8_lossy(&msg.payload.data))?;
+                }
+                stdout.flush()?;
+
+                if echo {
+                    let payload = ipmb::BytesMessage {
+                        format: msg.payload.format,
+                        data: msg.payload.data.clone(),
+                    };
+                    if let Some(reply) = msg.reply(payload) {
+                        sender.send(reply)?;
+                    }
+                }
+            }
+        }
+        Command::Endpoints { timeout } => {
+            let info = sender.bus_info(Some(Duration::from_millis(timeout)))?;
+            let now = SystemTime::now();
+
+            println!(
+                "{:<36}  {:>7}  {:>6}  {:>8}  {:>5}  LABEL",
+                "ID", "PID", "UID", "UPTIME", "QUEUE"
+            );
+            for (endpoint, controller) in iter::once((&info.controller, true))
+                .chain(info.endpoints.iter().map(|endpoint| (endpoint, false)))
+            {
+                let optional = |v: Option<u32>| v.map_or("-".to_string(), |v| v.to_string());
+                let uptime = now
+                    .duration_since(endpoint.connected_at)
+                    .unwrap_or_default()
+                    .as_secs();
+                println!(
+                    "{:<36}  {:>7}  {:>6}  {:>7}s  {:>5}  {}{}",
+                    endpoint.id,
+                    optional(endpoint.pid),
+                    optional(endpoint.uid),
+                    uptime,
+                    endpoint.queue_depth,
+                    endpoint.label.iter().collect::<Vec<_>>().join(","),
+                    if controller { " [controller]" } else { "" },
+                );
+            }
+            for (label_op, count) in &info.buffered {
+                println!("buffered for {label_op}: {count}");
+            }
+        }
+        Command::Ping {
+            label_op,
+            count,
+            size,
+            interval,
+            timeout,
+        } => {
+            let mut rtts = Vec::with_capacity(count);
+
+            for i in 0..count {
+                if i > 0 {
+                    std::thread::sleep(Duration::from_millis(interval));
+                }
+
+                let start = Instant::now();
+                let payload = ipmb::BytesMessage {
+                    format: 0,
+                    data: vec![0; size],
+                };
+                match sender.request::<ipmb::BytesMessage>(
+                    ipmb::Selector::unicast(label_op.clone()),
+                    payload,
+                    Some(Duration::from_millis(timeout)),
+                ) {
+                    Ok(reply) => {
+                        let rtt = start.elapsed();
+                        rtts.push(rtt);
+                        println!(
+                            "{} bytes from {}: seq={} time={:.3}ms",
+                            reply.payload.data.len(),
+                            reply
+                                .source()
+                                .map(|id| id.to_string())
+                                .unwrap_or_else(|| "?".to_string()),
+                            i,
+                            millis(rtt),
+                        );
+                    }
+                    Err(err) => println!("seq={i}: {err}"),
+                }
+            }
+
+            println!(
+                "{} requests, {} replies, {:.1}% lost",
+                count,
+                rtts.len(),
+                (count - rtts.len()) as f64 * 100. / count.max(1) as f64,
+            );
+            if let (Some(min), Some(max)) = (rtts.iter().min(), rtts.iter().max()) {
+                let avg = rtts.iter().sum::<Duration>() / rtts.len() as u32;
+                println!(
+                    "rtt min/avg/max = {:.3}/{:.3}/{:.3}ms",
+                    millis(*min),
+                    millis(avg),
+                    millis(*max),
+                );
+            }
+        }
+    }
+
+    drop(receiver);
+    Ok(())
+}
+
+fn millis(d: Duration) -> f64 {
+    d.as_micros() as f64 / 1000.
+}
+
+/// 16 bytes a line, offset first.
+fn write_hex(w: &mut impl Write, data: &[u8]) -> io::Result<()> {
+    for (i, chunk) in data.chunks(16).enumerate() {
+        write!(w, "{:08x} ", i * 16)?;
+        for b in chunk {
+            write!(w, " {b:02x}")?;
+        }
+        writeln!(w)?;
+    }
+    Ok(())
+}
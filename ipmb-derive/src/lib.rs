@@ -1,9 +1,12 @@
 use proc_macro::TokenStream;
 use quote::quote;
 use std::env;
-use syn::{parse_macro_input, Data, DeriveInput, Fields, Path};
+use syn::{
+    parse_macro_input, parse_quote, Attribute, Data, DeriveInput, Fields, Ident, Path, Token,
+};
 
-#[proc_macro_derive(MessageBox)]
+/// `#[ipmb(codec = ...)]` on the enum sets the codec of all variants, on a variant overrides it.
+#[proc_macro_derive(MessageBox, attributes(ipmb))]
 pub fn derive_message_box(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
 
@@ -18,6 +21,11 @@ pub fn derive_message_box(input: TokenStream) -> TokenStream {
 
     let indent = input.ident;
 
+    let default_codec: Path = match codec_attr(&input.attrs) {
+        Ok(codec) => codec.unwrap_or_else(|| parse_quote!(#crate_path::codec::Bincode)),
+        Err(err) => return err.to_compile_error().into(),
+    };
+
     let data_enum = if let Data::Enum(data_enum) = input.data {
         data_enum
     } else {
@@ -46,12 +54,32 @@ pub fn derive_message_box(input: TokenStream) -> TokenStream {
         })
         .collect();
 
+    let variants_codec = data_enum
+        .variants
+        .iter()
+        .map(|variant| Ok(codec_attr(&variant.attrs)?.unwrap_or_else(|| default_codec.clone())))
+        .collect::<syn::Result<Vec<_>>>();
+    let variants_codec = match variants_codec {
+        Ok(variants_codec) => variants_codec,
+        Err(err) => return err.to_compile_error().into(),
+    };
+
     let expanded = quote! {
         impl #crate_path::MessageBox for #indent {
             fn decode(uuid: type_uuid::Bytes, data: &[u8]) -> std::result::Result<Self, #crate_path::Error> {
                 match uuid {
                     #(<#variants_ty as type_uuid::TypeUuid>::UUID => {
-                        let variant: #variants_ty = #crate_path::decode(data)?;
+                        let variant: #variants_ty = <#variants_codec as #crate_path::Codec>::decode(data)?;
+                        Ok(Self::#variants_ident(variant))
+                    })*
+                    _ => Err(#crate_path::Error::TypeUuidNotFound),
+                }
+            }
+
+            fn decode_with(uuid: type_uuid::Bytes, codec: u8, data: &[u8]) -> std::result::Result<Self, #crate_path::Error> {
+                match uuid {
+                    #(<#variants_ty as type_uuid::TypeUuid>::UUID => {
+                        let variant: #variants_ty = #crate_path::codec::decode::<#variants_codec, _>(codec, data)?;
                         Ok(Self::#variants_ident(variant))
                     })*
                     _ => Err(#crate_path::Error::TypeUuidNotFound),
@@ -60,7 +88,7 @@ pub fn derive_message_box(input: TokenStream) -> TokenStream {
 
             fn encode(&self) -> std::result::Result<Vec<u8>, #crate_path::Error> {
                 match self {
-                    #(Self::#variants_ident(t) => #crate_path::encode(t),)*
+                    #(Self::#variants_ident(t) => <#variants_codec as #crate_path::Codec>::encode(t),)*
                 }
             }
 
@@ -69,8 +97,31 @@ pub fn derive_message_box(input: TokenStream) -> TokenStream {
                     #(Self::#variants_ident(_) => <#variants_ty as type_uuid::TypeUuid>::UUID,)*
                 }
             }
+
+            fn codec(&self) -> u8 {
+                match self {
+                    #(Self::#variants_ident(_) => <#variants_codec as #crate_path::Codec>::ID,)*
+                }
+            }
         }
     };
 
     TokenStream::from(expanded)
 }
+
+/// The codec of `#[ipmb(codec = ...)]`, an error points at a malformed attribute.
+fn codec_attr(attrs: &[Attribute]) -> syn::Result<Option<Path>> {
+    let Some(attr) = attrs.iter().find(|attr| attr.path.is_ident("ipmb")) else {
+        return Ok(None);
+    };
+
+    attr.parse_args_with(|input: syn::parse::ParseStream| {
+        let name: Ident = input.parse()?;
+        if name != "codec" {
+            return Err(syn::Error::new(name.span(), "expected `codec`"));
+        }
+        input.parse::<Token![=]>()?;
+        input.parse::<Path>()
+    })
+    .map(Some)
+}
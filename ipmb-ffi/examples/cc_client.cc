@@ -31,6 +31,7 @@ int main()
         selector.label_op = &op;
         selector.mode = ipmb_ffi::SelectorMode::kUnicast;
         selector.ttl = 0;
+        selector.priority = ipmb_ffi::Priority::kNormal;
 
         const uint8_t buffer[5] { 0, 1, 2, 3, 4 };
 
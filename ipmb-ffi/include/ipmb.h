@@ -1,9 +1,13 @@
 #pragma once
 
+#include <algorithm>
+#include <array>
 #include <functional>
 #include <initializer_list>
+#include <string>
 #include <tuple>
 #include <type_traits>
+#include <vector>
 #include "ipmb_ffi.h"
 
 namespace ipmb {
@@ -15,6 +19,9 @@ namespace ipmb {
         kVersionMismatch = 4,
         kTokenMismatch = 5,
         kPermissionDenied = 6,
+        kParse = 7,
+        kFull = 8,
+        kSpill = 9,
     };
 
     class Version {
@@ -54,6 +61,15 @@ namespace ipmb {
 
         LabelOp(const char* s);
 
+        static LabelOp prefix(const std::string& s);
+
+        static LabelOp glob(const std::string& s);
+
+        // On error the LabelOp matches nothing
+        static std::tuple<LabelOp, Error> parse(const std::string& s, uintptr_t* error_position = nullptr);
+
+        std::string to_string() const;
+
         void op_not();
 
         void op_and(LabelOp right);
@@ -61,6 +77,8 @@ namespace ipmb {
         void op_or(LabelOp right);
 
        private:
+        explicit LabelOp(ipmb_ffi::LabelOp raw);
+
         OwnedPtr<ipmb_ffi::LabelOp> ptr_;
 
         friend class Selector;
@@ -83,11 +101,13 @@ namespace ipmb {
 
     class Selector {
      public:
-      Selector(LabelOp label_op, ipmb_ffi::SelectorMode mode, uint32_t ttl);
+      Selector(LabelOp label_op, ipmb_ffi::SelectorMode mode, uint32_t ttl,
+               ipmb_ffi::Priority priority = ipmb_ffi::Priority::kNormal);
 
       LabelOp label_op;
       ipmb_ffi::SelectorMode mode;
       uint32_t ttl;
+      ipmb_ffi::Priority priority;
 
       ipmb_ffi::Selector as_ffi();
 
@@ -162,12 +182,38 @@ namespace ipmb {
       friend class Receiver;
     };
 
+    class EndpointInfo {
+     public:
+      std::array<uint8_t, 16> id;
+      std::vector<std::string> label;
+      // False where the platform doesn't report pid and uid
+      bool has_credentials;
+      uint32_t pid;
+      uint32_t uid;
+      // Milliseconds since the Unix epoch
+      uint64_t connected_at;
+      uintptr_t queue_depth;
+    };
+
+    class BusInfo {
+     public:
+      EndpointInfo controller;
+      std::vector<EndpointInfo> endpoints;
+      // Textual label ops and the number of messages buffered for them
+      std::vector<std::tuple<std::string, uintptr_t>> buffered;
+    };
+
     class Receiver;
 
     class Sender {
     public:
      Error send(Message message);
 
+     // kFull instead of blocking when the bus controller can't take the message
+     Error try_send(Message message);
+
+     std::tuple<BusInfo, Error> bus_info(uint32_t timeout);
+
     private:
         friend std::tuple<Sender, Receiver, Error> join(Options& options, uint32_t timeout);
 
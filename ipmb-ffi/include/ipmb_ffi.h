@@ -10,6 +10,13 @@ namespace ipmb_ffi {
 
 constexpr static const uint32_t TIMEOUT_INFINITE = ~0u;
 
+/// `kNormal` is 0 so a zero-initialized `Selector` has the default priority.
+enum class Priority {
+    kNormal = 0,
+    kLow = 1,
+    kHigh = 2,
+};
+
 enum class SelectorMode {
     kUnicast,
     kMulticast,
@@ -39,6 +46,20 @@ using Receiver = void*;
 /// Message
 using Message = void*;
 
+/// BusInfo
+using BusInfo = void*;
+
+/// An endpoint on the bus, `has_credentials` is false where the platform doesn't report pid and uid.
+struct EndpointInfo {
+    uint8_t id[16];
+    bool has_credentials;
+    uint32_t pid;
+    uint32_t uid;
+    /// Milliseconds since the Unix epoch.
+    uint64_t connected_at;
+    uintptr_t queue_depth;
+};
+
 /// MemoryRegistry
 using MemoryRegistry = void*;
 
@@ -53,6 +74,7 @@ struct Selector {
     const LabelOp *label_op;
     SelectorMode mode;
     uint32_t ttl;
+    Priority priority;
 };
 
 /// Kernel Object
@@ -72,6 +94,12 @@ constexpr static const ErrorCode ERROR_CODE_TOKEN_MISMATCH = -5;
 
 constexpr static const ErrorCode ERROR_CODE_PERMISSION_DENIED = -6;
 
+constexpr static const ErrorCode ERROR_CODE_PARSE = -7;
+
+constexpr static const ErrorCode ERROR_CODE_FULL = -8;
+
+constexpr static const ErrorCode ERROR_CODE_SPILL = -9;
+
 extern "C" {
 
 void ipmb_rstring_data(const RString *rstring, const char **ptr, uintptr_t *size);
@@ -90,6 +118,34 @@ void ipmb_sender_drop(Sender sender);
 
 ErrorCode ipmb_send(Sender *sender, Message message);
 
+/// Like `ipmb_send`, but returns `ERROR_CODE_FULL` instead of blocking.
+ErrorCode ipmb_try_send(Sender *sender, Message message);
+
+/// Ask the bus controller which endpoints are on the bus.
+ErrorCode ipmb_bus_info(Sender *sender, BusInfo *p_info, uint32_t timeout);
+
+void ipmb_bus_info_drop(BusInfo info);
+
+/// The number of endpoints on the bus, including the one of the bus controller at index 0.
+uintptr_t ipmb_bus_info_endpoint_count(const BusInfo *info);
+
+ErrorCode ipmb_bus_info_endpoint(const BusInfo *info, uintptr_t index, EndpointInfo *p_endpoint);
+
+/// Element `element` of the label of endpoint `index`, `ERROR_CODE_UNKNOWN` past the last one.
+ErrorCode ipmb_bus_info_endpoint_label(const BusInfo *info,
+                                       uintptr_t index,
+                                       uintptr_t element,
+                                       RString *p_element);
+
+/// The number of label ops that messages are buffered for.
+uintptr_t ipmb_bus_info_buffered_count(const BusInfo *info);
+
+/// The textual label op at `index` and the number of messages buffered for it.
+ErrorCode ipmb_bus_info_buffered(const BusInfo *info,
+                                 uintptr_t index,
+                                 RString *p_label_op,
+                                 uintptr_t *p_count);
+
 void ipmb_receiver_drop(Receiver receiver);
 
 ErrorCode ipmb_recv(Receiver *receiver, Message *p_message, uint32_t timeout);
@@ -167,6 +223,15 @@ LabelOp ipmb_label_op_bool(bool v);
 
 LabelOp ipmb_label_op_leaf(const char *s);
 
+LabelOp ipmb_label_op_prefix(const char *s);
+
+LabelOp ipmb_label_op_glob(const char *s);
+
+/// Parse the textual form of LabelOp, `error_position` receives the byte offset of a parse error.
+ErrorCode ipmb_label_op_parse(const char *s, LabelOp *p_label_op, uintptr_t *error_position);
+
+RString ipmb_label_op_to_string(const LabelOp *label_op);
+
 void ipmb_label_op_drop(LabelOp left);
 
 LabelOp ipmb_label_op_not(LabelOp left);
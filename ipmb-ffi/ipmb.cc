@@ -46,6 +46,49 @@ namespace ipmb {
             ipmb_ffi::ipmb_label_op_drop(raw);
           }) {}
 
+    LabelOp::LabelOp(ipmb_ffi::LabelOp raw)
+        : ptr_(raw, [](ipmb_ffi::LabelOp raw) {
+            ipmb_ffi::ipmb_label_op_drop(raw);
+          }) {}
+
+    LabelOp LabelOp::prefix(const std::string& s) {
+      return LabelOp(ipmb_ffi::ipmb_label_op_prefix(s.c_str()));
+    }
+
+    LabelOp LabelOp::glob(const std::string& s) {
+      return LabelOp(ipmb_ffi::ipmb_label_op_glob(s.c_str()));
+    }
+
+    std::tuple<LabelOp, Error> LabelOp::parse(const std::string& s, uintptr_t* error_position) {
+      ipmb_ffi::LabelOp raw = nullptr;
+      auto r = ipmb_ffi::ipmb_label_op_parse(s.c_str(), &raw, error_position);
+
+      switch (r) {
+        case ipmb_ffi::ERROR_CODE_SUCCESS:
+          return std::make_tuple(LabelOp(raw), Error::kSuccess);
+        // raw is null, never wrap it
+        case ipmb_ffi::ERROR_CODE_PARSE:
+          return std::make_tuple(LabelOp(false), Error::kParse);
+        default:
+          return std::make_tuple(LabelOp(false), Error::kUnknown);
+      }
+    }
+
+    std::string LabelOp::to_string() const {
+      auto raw = ptr_.get();
+      auto s_raw = ipmb_ffi::ipmb_label_op_to_string(&raw);
+
+      const char* s_ptr = nullptr;
+      uintptr_t s_len = 0;
+      ipmb_ffi::ipmb_rstring_data(&s_raw, &s_ptr, &s_len);
+
+      std::string s(s_ptr, s_len);
+
+      ipmb_ffi::ipmb_rstring_drop(s_raw);
+
+      return s;
+    }
+
     void LabelOp::op_not() {
       auto* raw = ipmb_ffi::ipmb_label_op_not(ptr_.release());
       ptr_.reset(raw);
@@ -79,14 +122,16 @@ namespace ipmb {
     }
 
     /// Selector
-    Selector::Selector(ipmb::LabelOp label_op, ipmb_ffi::SelectorMode mode, uint32_t ttl)
+    Selector::Selector(ipmb::LabelOp label_op, ipmb_ffi::SelectorMode mode, uint32_t ttl,
+                       ipmb_ffi::Priority priority)
             : label_op(std::move(label_op)),
               mode(mode),
-              ttl(ttl) {}
+              ttl(ttl),
+              priority(priority) {}
 
     ipmb_ffi::Selector Selector::as_ffi() {
       label_op_raw_ = label_op.ptr_.get();
-      ipmb_ffi::Selector selector{&label_op_raw_, mode, ttl};
+      ipmb_ffi::Selector selector{&label_op_raw_, mode, ttl, priority};
 
       return selector;
     }
@@ -273,10 +318,7 @@ namespace ipmb {
         : ptr_(raw,
                [](ipmb_ffi::Sender raw) { ipmb_ffi::ipmb_sender_drop(raw); }) {}
 
-    Error Sender::send(Message message) {
-      auto* raw = ptr_.get();
-      auto r = ipmb_ffi::ipmb_send(&raw, message.ptr_.release());
-
+    static Error send_error(ipmb_ffi::ErrorCode r) {
       switch (r) {
         case ipmb_ffi::ERROR_CODE_SUCCESS:
           return Error::kSuccess;
@@ -290,11 +332,94 @@ namespace ipmb {
           return Error::kVersionMismatch;
         case ipmb_ffi::ERROR_CODE_PERMISSION_DENIED:
           return Error::kPermissionDenied;
+        case ipmb_ffi::ERROR_CODE_FULL:
+          return Error::kFull;
+        case ipmb_ffi::ERROR_CODE_SPILL:
+          return Error::kSpill;
         default:
           return Error::kUnknown;
       }
     }
 
+    Error Sender::send(Message message) {
+      auto* raw = ptr_.get();
+      return send_error(ipmb_ffi::ipmb_send(&raw, message.ptr_.release()));
+    }
+
+    Error Sender::try_send(Message message) {
+      auto* raw = ptr_.get();
+      return send_error(ipmb_ffi::ipmb_try_send(&raw, message.ptr_.release()));
+    }
+
+    static std::string take_rstring(ipmb_ffi::RString raw) {
+      const char* ptr = nullptr;
+      uintptr_t len = 0;
+      ipmb_ffi::ipmb_rstring_data(&raw, &ptr, &len);
+
+      std::string s(ptr, len);
+
+      ipmb_ffi::ipmb_rstring_drop(raw);
+
+      return s;
+    }
+
+    std::tuple<BusInfo, Error> Sender::bus_info(uint32_t timeout) {
+      BusInfo info;
+      ipmb_ffi::BusInfo info_raw = nullptr;
+      auto* raw = ptr_.get();
+
+      switch (ipmb_ffi::ipmb_bus_info(&raw, &info_raw, timeout)) {
+        case ipmb_ffi::ERROR_CODE_SUCCESS:
+          break;
+        case ipmb_ffi::ERROR_CODE_TIMEOUT:
+          return std::make_tuple(info, Error::kTimeout);
+        case ipmb_ffi::ERROR_CODE_DECODE:
+          return std::make_tuple(info, Error::kDecode);
+        default:
+          return std::make_tuple(info, Error::kUnknown);
+      }
+
+      auto count = ipmb_ffi::ipmb_bus_info_endpoint_count(&info_raw);
+      for (uintptr_t i = 0; i < count; i++) {
+        ipmb_ffi::EndpointInfo ep_raw;
+        ipmb_ffi::ipmb_bus_info_endpoint(&info_raw, i, &ep_raw);
+
+        EndpointInfo ep;
+        std::copy(std::begin(ep_raw.id), std::end(ep_raw.id), ep.id.begin());
+        ep.has_credentials = ep_raw.has_credentials;
+        ep.pid = ep_raw.pid;
+        ep.uid = ep_raw.uid;
+        ep.connected_at = ep_raw.connected_at;
+        ep.queue_depth = ep_raw.queue_depth;
+
+        ipmb_ffi::RString element_raw;
+        for (uintptr_t j = 0;
+             ipmb_ffi::ipmb_bus_info_endpoint_label(&info_raw, i, j, &element_raw) ==
+             ipmb_ffi::ERROR_CODE_SUCCESS;
+             j++) {
+          ep.label.push_back(take_rstring(element_raw));
+        }
+
+        if (i == 0) {
+          info.controller = std::move(ep);
+        } else {
+          info.endpoints.push_back(std::move(ep));
+        }
+      }
+
+      count = ipmb_ffi::ipmb_bus_info_buffered_count(&info_raw);
+      for (uintptr_t i = 0; i < count; i++) {
+        ipmb_ffi::RString label_op_raw;
+        uintptr_t buffered = 0;
+        ipmb_ffi::ipmb_bus_info_buffered(&info_raw, i, &label_op_raw, &buffered);
+        info.buffered.emplace_back(take_rstring(label_op_raw), buffered);
+      }
+
+      ipmb_ffi::ipmb_bus_info_drop(info_raw);
+
+      return std::make_tuple(info, Error::kSuccess);
+    }
+
     /// Receiver
     Receiver::Receiver(ipmb_ffi::Receiver raw)
         : ptr_(raw, [](ipmb_ffi::Receiver raw) {
@@ -4,7 +4,7 @@ use std::{
     ops::{Deref, DerefMut},
     os::raw::c_char,
     ptr, slice,
-    time::Duration,
+    time::{Duration, UNIX_EPOCH},
 };
 
 type ErrorCode = i32;
@@ -16,6 +16,9 @@ pub const ERROR_CODE_DECODE: ErrorCode = -3;
 pub const ERROR_CODE_VERSION_MISMATCH: ErrorCode = -4;
 pub const ERROR_CODE_TOKEN_MISMATCH: ErrorCode = -5;
 pub const ERROR_CODE_PERMISSION_DENIED: ErrorCode = -6;
+pub const ERROR_CODE_PARSE: ErrorCode = -7;
+pub const ERROR_CODE_FULL: ErrorCode = -8;
+pub const ERROR_CODE_SPILL: ErrorCode = -9;
 
 pub const TIMEOUT_INFINITE: u32 = !0u32;
 
@@ -118,13 +121,11 @@ pub unsafe extern "C" fn ipmb_join(
         Err(_) => return ERROR_CODE_UNKNOWN,
     };
 
+    let mut join_options = ipmb::Options::new(identifier, (*options.label).clone(), token);
+    join_options.controller_affinity = options.controller_affinity;
+
     match ipmb::join::<ipmb::BytesMessage, ipmb::BytesMessage>(
-        ipmb::Options {
-            identifier,
-            label: (*options.label).clone(),
-            token,
-            controller_affinity: options.controller_affinity,
-        },
+        join_options,
         if timeout == TIMEOUT_INFINITE {
             None
         } else {
@@ -158,15 +159,161 @@ pub unsafe extern "C" fn ipmb_sender_drop(sender: Sender) {}
 
 #[no_mangle]
 pub unsafe extern "C" fn ipmb_send(sender: &mut Sender, message: Message) -> ErrorCode {
-    match sender.send(message.into()) {
+    send_error_code(sender.send(message.into()))
+}
+
+/// Like `ipmb_send`, but returns `ERROR_CODE_FULL` instead of blocking.
+#[no_mangle]
+pub unsafe extern "C" fn ipmb_try_send(sender: &mut Sender, message: Message) -> ErrorCode {
+    send_error_code(sender.try_send(message.into()))
+}
+
+fn send_error_code(r: Result<(), ipmb::SendError>) -> ErrorCode {
+    match r {
         Ok(_) => ERROR_CODE_SUCCESS,
         Err(ipmb::SendError::Timeout) => ERROR_CODE_TIMEOUT,
         Err(ipmb::SendError::VersionMismatch(_)) => ERROR_CODE_VERSION_MISMATCH,
         Err(ipmb::SendError::TokenMismatch) => ERROR_CODE_TOKEN_MISMATCH,
         Err(ipmb::SendError::PermissionDenied) => ERROR_CODE_PERMISSION_DENIED,
+        Err(ipmb::SendError::Full) => ERROR_CODE_FULL,
+        Err(ipmb::SendError::Spill) => ERROR_CODE_SPILL,
+    }
+}
+
+/// Ask the bus controller which endpoints are on the bus.
+#[no_mangle]
+pub unsafe extern "C" fn ipmb_bus_info(
+    sender: &mut Sender,
+    p_info: *mut BusInfo,
+    timeout: u32,
+) -> ErrorCode {
+    match sender.bus_info(if timeout == TIMEOUT_INFINITE {
+        None
+    } else {
+        Some(Duration::from_millis(timeout as _))
+    }) {
+        Ok(info) => {
+            ptr::write(p_info, info.into());
+            ERROR_CODE_SUCCESS
+        }
+        Err(ipmb::RequestError::Timeout | ipmb::RequestError::Send(ipmb::SendError::Timeout)) => {
+            ERROR_CODE_TIMEOUT
+        }
+        Err(ipmb::RequestError::Recv(ipmb::RecvError::Decode(_))) => ERROR_CODE_DECODE,
+        Err(_) => ERROR_CODE_UNKNOWN,
+    }
+}
+
+/// BusInfo
+#[repr(transparent)]
+pub struct BusInfo(*mut c_void);
+opaque_type!(BusInfo => ipmb::BusInfo);
+
+#[allow(unused_variables)]
+#[no_mangle]
+pub unsafe extern "C" fn ipmb_bus_info_drop(info: BusInfo) {}
+
+/// An endpoint on the bus, `has_credentials` is false where the platform doesn't report pid and uid.
+#[repr(C)]
+pub struct EndpointInfo {
+    id: [u8; 16],
+    has_credentials: bool,
+    pid: u32,
+    uid: u32,
+    /// Milliseconds since the Unix epoch.
+    connected_at: u64,
+    queue_depth: usize,
+}
+
+impl BusInfo {
+    /// Index 0 is the endpoint of the bus controller.
+    fn endpoint(&self, index: usize) -> Option<&ipmb::EndpointInfo> {
+        match index {
+            0 => Some(&self.controller),
+            _ => self.endpoints.get(index - 1),
+        }
+    }
+}
+
+/// The number of endpoints on the bus, including the one of the bus controller at index 0.
+#[no_mangle]
+pub unsafe extern "C" fn ipmb_bus_info_endpoint_count(info: &BusInfo) -> usize {
+    info.endpoints.len() + 1
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ipmb_bus_info_endpoint(
+    info: &BusInfo,
+    index: usize,
+    p_endpoint: *mut EndpointInfo,
+) -> ErrorCode {
+    let Some(ep) = info.endpoint(index) else {
+        return ERROR_CODE_UNKNOWN;
+    };
+
+    ptr::write(
+        p_endpoint,
+        EndpointInfo {
+            id: *ep.id.as_bytes(),
+            has_credentials: ep.pid.is_some(),
+            pid: ep.pid.unwrap_or_default(),
+            uid: ep.uid.unwrap_or_default(),
+            connected_at: ep
+                .connected_at
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or_default(),
+            queue_depth: ep.queue_depth,
+        },
+    );
+    ERROR_CODE_SUCCESS
+}
+
+/// Element `element` of the label of endpoint `index`, `ERROR_CODE_UNKNOWN` past the last one.
+#[no_mangle]
+pub unsafe extern "C" fn ipmb_bus_info_endpoint_label(
+    info: &BusInfo,
+    index: usize,
+    element: usize,
+    p_element: *mut RString,
+) -> ErrorCode {
+    match info
+        .endpoint(index)
+        .and_then(|ep| ep.label.iter().nth(element))
+    {
+        Some(s) => {
+            ptr::write(p_element, s.to_owned().into());
+            ERROR_CODE_SUCCESS
+        }
+        None => ERROR_CODE_UNKNOWN,
     }
 }
 
+/// The number of label ops that messages are buffered for.
+#[no_mangle]
+pub unsafe extern "C" fn ipmb_bus_info_buffered_count(info: &BusInfo) -> usize {
+    info.buffered.len()
+}
+
+/// The textual label op at `index` and the number of messages buffered for it.
+#[no_mangle]
+pub unsafe extern "C" fn ipmb_bus_info_buffered(
+    info: &BusInfo,
+    index: usize,
+    p_label_op: *mut RString,
+    p_count: *mut usize,
+) -> ErrorCode {
+    let Some((label_op, count)) = info.buffered.get(index) else {
+        return ERROR_CODE_UNKNOWN;
+    };
+
+    ptr::write(p_label_op, label_op.to_string().into());
+    if !p_count.is_null() {
+        *p_count = *count;
+    }
+    ERROR_CODE_SUCCESS
+}
+
 /// Receiver
 #[repr(transparent)]
 pub struct Receiver(*mut c_void);
@@ -196,6 +343,9 @@ pub unsafe extern "C" fn ipmb_recv(
         Err(ipmb::RecvError::VersionMismatch(_)) => ERROR_CODE_VERSION_MISMATCH,
         Err(ipmb::RecvError::TokenMismatch) => ERROR_CODE_TOKEN_MISMATCH,
         Err(ipmb::RecvError::PermissionDenied) => ERROR_CODE_PERMISSION_DENIED,
+        // Options::ordered is not exposed
+        Err(ipmb::RecvError::Gap { .. }) => ERROR_CODE_UNKNOWN,
+        Err(ipmb::RecvError::ReaderClosed) => ERROR_CODE_UNKNOWN,
     }
 }
 
@@ -479,6 +629,51 @@ pub unsafe extern "C" fn ipmb_label_op_leaf(s: *const c_char) -> LabelOp {
     ipmb::LabelOp::from(s).into()
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn ipmb_label_op_prefix(s: *const c_char) -> LabelOp {
+    let s = CStr::from_ptr(s).to_string_lossy();
+    ipmb::LabelOp::prefix(s).into()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ipmb_label_op_glob(s: *const c_char) -> LabelOp {
+    let s = CStr::from_ptr(s).to_string_lossy();
+    ipmb::LabelOp::glob(s).into()
+}
+
+/// Parse the textual form of LabelOp, `error_position` receives the byte offset of a parse error.
+#[no_mangle]
+pub unsafe extern "C" fn ipmb_label_op_parse(
+    s: *const c_char,
+    p_label_op: *mut LabelOp,
+    error_position: *mut usize,
+) -> ErrorCode {
+    let s = match CStr::from_ptr(s).to_str() {
+        Ok(s) => s,
+        Err(_) => return ERROR_CODE_UNKNOWN,
+    };
+
+    match ipmb::LabelOp::parse(s) {
+        Ok(label_op) => {
+            if !p_label_op.is_null() {
+                ptr::write(p_label_op, label_op.into());
+            }
+            ERROR_CODE_SUCCESS
+        }
+        Err(err) => {
+            if !error_position.is_null() {
+                *error_position = err.position();
+            }
+            ERROR_CODE_PARSE
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ipmb_label_op_to_string(label_op: &LabelOp) -> RString {
+    label_op.to_string().into()
+}
+
 #[allow(unused_variables)]
 #[no_mangle]
 pub unsafe extern "C" fn ipmb_label_op_drop(left: LabelOp) {}
@@ -508,6 +703,7 @@ pub struct Selector {
     label_op: &'static LabelOp,
     mode: SelectorMode,
     ttl: u32,
+    priority: Priority,
 }
 
 impl From<Selector> for ipmb::Selector {
@@ -517,6 +713,11 @@ impl From<Selector> for ipmb::Selector {
             SelectorMode::kMulticast => ipmb::Selector::multicast((*v.label_op).clone()),
         };
         s.ttl = Duration::from_millis(v.ttl as _);
+        s.priority = match v.priority {
+            Priority::kLow => ipmb::Priority::Low,
+            Priority::kNormal => ipmb::Priority::Normal,
+            Priority::kHigh => ipmb::Priority::High,
+        };
         s
     }
 }
@@ -529,6 +730,16 @@ pub enum SelectorMode {
     kMulticast,
 }
 
+/// `kNormal` is 0 so a zero-initialized `Selector` has the default priority.
+#[allow(non_camel_case_types)]
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+pub enum Priority {
+    kNormal = 0,
+    kLow = 1,
+    kHigh = 2,
+}
+
 /// Options
 #[repr(C)]
 pub struct Options {
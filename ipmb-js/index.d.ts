@@ -7,10 +7,16 @@ export const enum SelectorMode {
   Unicast = 0,
   Multicast = 1
 }
+export const enum Priority {
+  Low = 0,
+  Normal = 1,
+  High = 2
+}
 export interface Selector {
   labelOp: LabelOp
   mode: SelectorMode
   ttl: number
+  priority?: Priority
 }
 export interface Options {
   identifier: string
@@ -22,9 +28,30 @@ export interface BytesMessage {
   format: number
   data: Buffer
 }
+export interface EndpointInfo {
+  id: string
+  label: Array<string>
+  pid?: number
+  uid?: number
+  /** Milliseconds since the unix epoch. */
+  connectedAt: number
+  queueDepth: number
+}
+export interface Buffered {
+  labelOp: string
+  count: number
+}
+export interface BusInfo {
+  controller: EndpointInfo
+  endpoints: Array<EndpointInfo>
+  buffered: Array<Buffered>
+}
 export declare function join(options: Options, timeout?: number | undefined | null): { sender: Sender, receiver: Receiver }
 export declare class LabelOp {
   constructor(v: boolean | string)
+  static prefix(s: string): LabelOp
+  static glob(s: string): LabelOp
+  static parse(s: string): LabelOp
   not(): void
   and(right: LabelOp): void
   or(right: LabelOp): void
@@ -38,6 +65,8 @@ export declare class MemoryRegion {
 }
 export declare class Sender {
   send(selector: Selector, bytesMessage: BytesMessage, buffers: Array<Buffer>): void
+  /** Resolves once the bus controller answered, the query runs off the main thread. */
+  busInfo(timeout?: number | undefined | null): Promise<BusInfo>
 }
 export declare class Receiver {
   recv(timeout?: number | undefined | null): Promise<{ bytesMessage: BytesMessage, objects: Array<Object>, memoryRegions: Array<MemoryRegion> }>
@@ -4,7 +4,7 @@ use std::{
     ffi, mem, ptr,
     sync::{mpsc, Arc, Mutex},
     thread,
-    time::{Duration, Instant},
+    time::{Duration, Instant, UNIX_EPOCH},
 };
 
 #[napi]
@@ -13,6 +13,13 @@ pub enum SelectorMode {
     Multicast,
 }
 
+#[napi]
+pub enum Priority {
+    Low,
+    Normal,
+    High,
+}
+
 #[napi]
 pub struct LabelOp(ipmb::LabelOp);
 
@@ -35,6 +42,24 @@ impl LabelOp {
         }))
     }
 
+    #[napi(factory)]
+    pub fn prefix(s: String) -> Self {
+        Self(ipmb::LabelOp::prefix(s))
+    }
+
+    #[napi(factory)]
+    pub fn glob(s: String) -> Self {
+        Self(ipmb::LabelOp::glob(s))
+    }
+
+    #[napi(factory)]
+    pub fn parse(s: String) -> Result<Self> {
+        match ipmb::LabelOp::parse(&s) {
+            Ok(v) => Ok(Self(v)),
+            Err(err) => Err(Error::new(Status::InvalidArg, err.to_string())),
+        }
+    }
+
     #[napi]
     pub fn not(&mut self) {
         self.0 = !self.0.clone();
@@ -52,7 +77,7 @@ impl LabelOp {
 
     #[napi]
     pub fn to_string(&self) -> String {
-        format!("{:?}", self.0)
+        self.0.to_string()
     }
 }
 
@@ -61,6 +86,7 @@ pub struct Selector {
     pub label_op: ClassInstance<LabelOp>,
     pub mode: SelectorMode,
     pub ttl: u32,
+    pub priority: Option<Priority>,
 }
 
 impl From<Selector> for ipmb::Selector {
@@ -70,6 +96,11 @@ impl From<Selector> for ipmb::Selector {
             SelectorMode::Multicast => ipmb::Selector::multicast(selector.label_op.0.clone()),
         };
         ipmb_selector.ttl = Duration::from_millis(selector.ttl as _);
+        ipmb_selector.priority = match selector.priority {
+            Some(Priority::Low) => ipmb::Priority::Low,
+            Some(Priority::Normal) | None => ipmb::Priority::Normal,
+            Some(Priority::High) => ipmb::Priority::High,
+        };
         ipmb_selector
     }
 }
@@ -88,6 +119,47 @@ pub struct BytesMessage {
     pub data: Buffer,
 }
 
+#[napi(object)]
+pub struct EndpointInfo {
+    pub id: String,
+    pub label: Vec<String>,
+    pub pid: Option<u32>,
+    pub uid: Option<u32>,
+    /// Milliseconds since the unix epoch.
+    pub connected_at: f64,
+    pub queue_depth: u32,
+}
+
+impl From<ipmb::EndpointInfo> for EndpointInfo {
+    fn from(info: ipmb::EndpointInfo) -> Self {
+        Self {
+            id: info.id.to_string(),
+            label: info.label.iter().map(ToOwned::to_owned).collect(),
+            pid: info.pid,
+            uid: info.uid,
+            connected_at: info
+                .connected_at
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as _,
+            queue_depth: info.queue_depth as _,
+        }
+    }
+}
+
+#[napi(object)]
+pub struct Buffered {
+    pub label_op: String,
+    pub count: u32,
+}
+
+#[napi(object)]
+pub struct BusInfo {
+    pub controller: EndpointInfo,
+    pub endpoints: Vec<EndpointInfo>,
+    pub buffered: Vec<Buffered>,
+}
+
 #[napi]
 pub struct Object(ipmb::Object);
 
@@ -163,6 +235,46 @@ impl Sender {
 
         Ok(())
     }
+
+    /// Resolves once the bus controller answered, the query runs off the main thread.
+    #[napi(ts_return_type = "Promise<BusInfo>")]
+    pub fn bus_info(&self, timeout: Option<u32>) -> AsyncTask<BusInfoTask> {
+        AsyncTask::new(BusInfoTask {
+            sender: self.sender.clone(),
+            timeout: timeout.map(|timeout| Duration::from_millis(timeout as _)),
+        })
+    }
+}
+
+pub struct BusInfoTask {
+    sender: ipmb::EndpointSender<ipmb::BytesMessage>,
+    timeout: Option<Duration>,
+}
+
+impl Task for BusInfoTask {
+    type Output = ipmb::BusInfo;
+    type JsValue = BusInfo;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        self.sender
+            .bus_info(self.timeout)
+            .map_err(|err| Error::new(Status::GenericFailure, format!("{:?}", err)))
+    }
+
+    fn resolve(&mut self, _env: Env, info: Self::Output) -> Result<Self::JsValue> {
+        Ok(BusInfo {
+            controller: info.controller.into(),
+            endpoints: info.endpoints.into_iter().map(Into::into).collect(),
+            buffered: info
+                .buffered
+                .into_iter()
+                .map(|(label_op, count)| Buffered {
+                    label_op: label_op.to_string(),
+                    count: count as _,
+                })
+                .collect(),
+        })
+    }
 }
 
 #[napi]
@@ -231,13 +343,12 @@ impl Receiver {
 
 #[napi(ts_return_type = "{ sender: Sender, receiver: Receiver }")]
 pub fn join(options: Options, timeout: Option<u32>, mut env: Env) -> Result<napi::JsObject> {
+    let mut join_options =
+        ipmb::Options::new(options.identifier, options.label.into(), options.token);
+    join_options.controller_affinity = options.controller_affinity;
+
     let (sender, mut receiver) = ipmb::join::<ipmb::BytesMessage, ipmb::BytesMessage>(
-        ipmb::Options {
-            identifier: options.identifier,
-            label: options.label.into(),
-            token: options.token,
-            controller_affinity: options.controller_affinity,
-        },
+        join_options,
         timeout.map(|v| Duration::from_millis(v as _)),
     )
     .map_err(|err| Error::new(Status::GenericFailure, format!("{:?}", err)))?;
@@ -300,12 +411,12 @@ pub fn join(options: Options, timeout: Option<u32>, mut env: Env) -> Result<napi
                 | ipmb::RecvError::TokenMismatch
                 | ipmb::RecvError::PermissionDenied,
             ) => {
-                tsfn.call(DelegateAction::Recv(r));
+                tsfn.call(DelegateAction::Recv(Box::new(r)));
                 tsfn.destroy();
                 break;
             }
             _ => {
-                tsfn.call(DelegateAction::Recv(r));
+                tsfn.call(DelegateAction::Recv(Box::new(r)));
             }
         }
     });
@@ -431,7 +542,7 @@ impl LocalBuffer {
 enum DelegateAction {
     CleanTimeout,
     Close(ThreadsafeFunction),
-    Recv(std::result::Result<ipmb::Message<ipmb::BytesMessage>, ipmb::RecvError>),
+    Recv(Box<std::result::Result<ipmb::Message<ipmb::BytesMessage>, ipmb::RecvError>>),
 }
 
 extern "C" fn delegate_receiver(
@@ -451,6 +562,7 @@ extern "C" fn delegate_receiver(
             DelegateAction::Close(tsfn) => local.close(env, tsfn),
             DelegateAction::CleanTimeout => local.clean_timeout(env),
             DelegateAction::Recv(r) => {
+                let r = *r;
                 if local.deferred_list.is_empty() {
                     local.messages.push(r);
                 } else {
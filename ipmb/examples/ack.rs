@@ -0,0 +1,65 @@
+use ipmb::label;
+use std::{env, process, process::Command, thread, time::Duration};
+
+fn main() {
+    let mut builder = env_logger::Builder::new();
+    builder.filter_level(log::LevelFilter::Info);
+    builder.parse_default_env();
+    builder.init();
+
+    let mut args = env::args();
+    let command = args.next().unwrap();
+
+    match args.next() {
+        None => {
+            let (sender, _receiver) = ipmb::join::<String, ()>(
+                ipmb::Options::new("com.ipmb.ack", label!("dispatcher"), ""),
+                None,
+            )
+            .expect("Join com.ipmb.ack failed");
+
+            let mut child = Command::new(&command).arg("crash").spawn().unwrap();
+            thread::sleep(Duration::from_millis(500));
+
+            for i in 0..5 {
+                let mut selector = ipmb::Selector::unicast("worker");
+                selector.reliable = true;
+                // Wait for a worker when none is on the bus
+                selector.ttl = Duration::from_secs(5);
+
+                sender
+                    .send(ipmb::Message::new(selector, format!("job {i}")))
+                    .unwrap();
+            }
+
+            child.wait().unwrap();
+
+            // Takes over the jobs the crashed worker didn't ack
+            let mut child = Command::new(&command).arg("worker").spawn().unwrap();
+            child.wait().unwrap();
+        }
+        Some(role) => {
+            let (_sender, mut receiver) = ipmb::join::<(), String>(
+                ipmb::Options::new("com.ipmb.ack", label!("worker"), ""),
+                None,
+            )
+            .expect("Join com.ipmb.ack failed");
+
+            let mut handled = 0;
+            while let Ok(msg) = receiver.recv(Some(Duration::from_secs(2))) {
+                if role == "crash" && handled == 2 {
+                    log::info!("{role}: crash on {}", msg.payload);
+                    process::exit(1);
+                }
+
+                log::info!(
+                    "{role}: {}, redelivered {} times",
+                    msg.payload,
+                    msg.redelivered()
+                );
+                receiver.ack(&msg);
+                handled += 1;
+            }
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use futures::StreamExt;
+use ipmb::label;
+use std::{env, process::Command, time::Duration};
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let mut builder = env_logger::Builder::new();
+    builder.filter_level(log::LevelFilter::Info);
+    builder.parse_default_env();
+    builder.init();
+
+    let mut args = env::args();
+    let command = args.next().unwrap();
+
+    match args.next() {
+        None => {
+            let (_sender, mut receiver) = ipmb::join::<String, String>(
+                ipmb::Options::new("com.ipmb.async", label!("receiver"), ""),
+                None,
+            )
+            .expect("Join com.ipmb.async failed");
+
+            let mut child = Command::new(command).arg("sender").spawn().unwrap();
+
+            let mut count = 0;
+            while let Some(r) = receiver.next().await {
+                let msg = r.expect("Receive message failed");
+                log::info!("received: {}", msg.payload);
+
+                count += 1;
+                if count == 5 {
+                    break;
+                }
+            }
+
+            child.kill().unwrap();
+            child.wait().unwrap();
+        }
+        Some(_) => {
+            let (sender, _receiver) = ipmb::join::<String, String>(
+                ipmb::Options::new("com.ipmb.async", label!("sender"), ""),
+                None,
+            )
+            .expect("Join com.ipmb.async failed");
+
+            let task = tokio::spawn(async move {
+                for i in 0.. {
+                    let msg =
+                        ipmb::Message::new(ipmb::Selector::unicast("receiver"), i.to_string());
+                    if sender.send_async(msg).await.is_err() {
+                        break;
+                    }
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+                }
+            });
+            let _ = task.await;
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use ipmb::label;
+use serde::{Deserialize, Serialize};
+use std::{env, num::NonZeroUsize, process::Command, thread, time::Duration};
+use type_uuid::TypeUuid;
+
+#[derive(Debug, Serialize, Deserialize, TypeUuid)]
+#[uuid = "b2b6a4b6-7b0f-4a55-9d1d-1f3f3b6b2c51"]
+struct Frame {
+    seq: u32,
+    data: Vec<u8>,
+}
+
+fn main() {
+    let mut builder = env_logger::Builder::new();
+    builder.filter_level(log::LevelFilter::Info);
+    builder.parse_default_env();
+    builder.init();
+
+    let mut args = env::args();
+    let command = args.next().unwrap();
+
+    match args.next() {
+        None => {
+            let (sender, _receiver) = ipmb::join::<Frame, ()>(
+                ipmb::Options::new("com.ipmb.back_pressure", label!("producer"), ""),
+                None,
+            )
+            .expect("Join com.ipmb.back_pressure failed");
+
+            let mut child = Command::new(command).arg("consumer").spawn().unwrap();
+            thread::sleep(Duration::from_secs(1));
+
+            for seq in 0..100 {
+                let msg = ipmb::Message::new(
+                    ipmb::Selector::unicast("consumer"),
+                    Frame {
+                        seq,
+                        data: vec![0; 64 * 1024],
+                    },
+                );
+                match sender.send_timeout(msg, Duration::from_millis(100)) {
+                    Ok(_) => {}
+                    Err(err) => log::warn!("send {seq}: {err}"),
+                }
+            }
+
+            child.wait().unwrap();
+        }
+        Some(_) => {
+            // Keep at most 4 frames for this slow consumer, older ones are dropped.
+            let mut options = ipmb::Options::new("com.ipmb.back_pressure", label!("consumer"), "");
+            options.queue_capacity = NonZeroUsize::new(4).unwrap();
+            options.overflow = ipmb::Overflow::DropOldest;
+
+            let (_sender, mut receiver) =
+                ipmb::join::<(), Frame>(options, None).expect("Join com.ipmb.back_pressure failed");
+
+            while let Ok(msg) = receiver.recv(Some(Duration::from_secs(2))) {
+                log::info!("recv: {}", msg.payload.seq);
+                thread::sleep(Duration::from_millis(50));
+            }
+        }
+    }
+}
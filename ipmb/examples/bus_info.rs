@@ -0,0 +1,60 @@
+use ipmb::label;
+use std::{env, process::Command, thread, time::Duration};
+
+fn main() {
+    let mut builder = env_logger::Builder::new();
+    builder.filter_level(log::LevelFilter::Info);
+    builder.parse_default_env();
+    builder.init();
+
+    let mut args = env::args();
+    let command = args.next().unwrap();
+
+    match args.next() {
+        None => {
+            let (sender, _receiver) = ipmb::join::<(), ()>(
+                ipmb::Options::new("com.ipmb.bus_info", label!("console"), ""),
+                None,
+            )
+            .expect("Join com.ipmb.bus_info failed");
+
+            let mut children: Vec<_> = ["earth", "mars"]
+                .into_iter()
+                .map(|name| Command::new(&command).arg(name).spawn().unwrap())
+                .collect();
+            thread::sleep(Duration::from_millis(500));
+
+            let mut selector = ipmb::Selector::unicast("venus");
+            selector.ttl = Duration::from_secs(10);
+            sender.send(ipmb::Message::new(selector, ())).unwrap();
+
+            let info = sender.bus_info(Some(Duration::from_secs(1))).unwrap();
+            for ep in std::iter::once(&info.controller).chain(&info.endpoints) {
+                log::info!(
+                    "{} {:?} pid: {:?} uid: {:?} queue: {}",
+                    ep.id,
+                    ep.label,
+                    ep.pid,
+                    ep.uid,
+                    ep.queue_depth
+                );
+            }
+            for (label_op, count) in &info.buffered {
+                log::info!("buffered for {}: {}", label_op, count);
+            }
+
+            for child in &mut children {
+                child.wait().unwrap();
+            }
+        }
+        Some(name) => {
+            let (_sender, _receiver) = ipmb::join::<(), ()>(
+                ipmb::Options::new("com.ipmb.bus_info", label!("planet", name), ""),
+                None,
+            )
+            .expect("Join com.ipmb.bus_info failed");
+
+            thread::sleep(Duration::from_secs(1));
+        }
+    }
+}
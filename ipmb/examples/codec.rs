@@ -0,0 +1,66 @@
+use ipmb::{label, MessageBox};
+use serde::{Deserialize, Serialize};
+use std::{env, process::Command, thread, time::Duration};
+use type_uuid::TypeUuid;
+
+#[derive(Debug, Serialize, Deserialize, TypeUuid)]
+#[uuid = "3f0c2a4e-6d6b-4d0c-9a57-5e0d1f3b8c21"]
+struct Status {
+    name: String,
+    load: f32,
+}
+
+// Peers written in other languages can produce JSON
+#[derive(Debug, MessageBox)]
+#[ipmb(codec = ipmb::codec::Json)]
+enum JsonMessage {
+    Status(Status),
+}
+
+fn main() {
+    let mut builder = env_logger::Builder::new();
+    builder.filter_level(log::LevelFilter::Info);
+    builder.parse_default_env();
+    builder.init();
+
+    let mut args = env::args();
+    let command = args.next().unwrap();
+
+    match args.next() {
+        None => {
+            // Encodes with bincode, but decodes whatever codec the sender used
+            let (_sender, mut receiver) = ipmb::join::<(), Status>(
+                ipmb::Options::new("com.ipmb.codec", label!("monitor"), ""),
+                None,
+            )
+            .expect("Join com.ipmb.codec failed");
+
+            let mut child = Command::new(command).arg("reporter").spawn().unwrap();
+
+            while let Ok(msg) = receiver.recv(Some(Duration::from_secs(2))) {
+                log::info!("recv: {:?}", msg.payload);
+            }
+
+            child.wait().unwrap();
+        }
+        Some(_) => {
+            let (sender, _receiver) = ipmb::join::<JsonMessage, ()>(
+                ipmb::Options::new("com.ipmb.codec", label!("reporter"), ""),
+                None,
+            )
+            .expect("Join com.ipmb.codec failed");
+
+            thread::sleep(Duration::from_millis(500));
+
+            sender
+                .send(ipmb::Message::new(
+                    ipmb::Selector::unicast("monitor"),
+                    JsonMessage::Status(Status {
+                        name: "reporter".to_string(),
+                        load: 0.5,
+                    }),
+                ))
+                .unwrap();
+        }
+    }
+}
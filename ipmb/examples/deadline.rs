@@ -0,0 +1,53 @@
+use ipmb::label;
+use std::{env, process::Command, thread, time::Duration};
+
+fn main() {
+    let mut builder = env_logger::Builder::new();
+    builder.filter_level(log::LevelFilter::Info);
+    builder.parse_default_env();
+    builder.init();
+
+    let mut args = env::args();
+    let command = args.next().unwrap();
+
+    match args.next() {
+        None => {
+            let (sender, _receiver) = ipmb::join::<String, ()>(
+                ipmb::Options::new("com.ipmb.deadline", label!("pointer"), ""),
+                None,
+            )
+            .expect("Join com.ipmb.deadline failed");
+
+            let mut child = Command::new(command).arg("cursor").spawn().unwrap();
+            thread::sleep(Duration::from_millis(500));
+
+            for i in 0..20 {
+                // A cursor position is useless once the next one is due
+                let mut selector = ipmb::Selector::unicast("cursor");
+                selector.expire_after(Duration::from_millis(200));
+
+                sender
+                    .send(ipmb::Message::new(selector, format!("position {i}")))
+                    .unwrap();
+                thread::sleep(Duration::from_millis(50));
+            }
+
+            child.wait().unwrap();
+        }
+        Some(_) => {
+            let (_sender, mut receiver) = ipmb::join::<(), String>(
+                ipmb::Options::new("com.ipmb.deadline", label!("cursor"), ""),
+                None,
+            )
+            .expect("Join com.ipmb.deadline failed");
+
+            // Stalled, the positions sent meanwhile expire
+            thread::sleep(Duration::from_secs(1));
+
+            while let Ok(msg) = receiver.recv(Some(Duration::from_secs(2))) {
+                log::info!("recv: {}", msg.payload);
+            }
+            log::info!("expired: {}", receiver.expired_count());
+        }
+    }
+}
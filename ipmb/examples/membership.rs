@@ -0,0 +1,56 @@
+use ipmb::{label, MembershipEvent};
+use std::{env, process::Command, thread, time::Duration};
+
+fn main() {
+    let mut builder = env_logger::Builder::new();
+    builder.filter_level(log::LevelFilter::Info);
+    builder.parse_default_env();
+    builder.init();
+
+    let mut args = env::args();
+    let command = args.next().unwrap();
+
+    match args.next() {
+        None => {
+            let mut options = ipmb::Options::new("com.ipmb.membership", label!("supervisor"), "");
+            options.membership = Some("worker".into());
+
+            let (_sender, mut receiver) =
+                ipmb::join::<(), MembershipEvent>(options, None).expect("Join failed");
+
+            for i in 0..2 {
+                let mut child = Command::new(&command)
+                    .arg(format!("worker{i}"))
+                    .spawn()
+                    .unwrap();
+                child.wait().unwrap();
+            }
+
+            let mut left = 0;
+            while left < 2 {
+                match receiver.recv(None).map(|msg| msg.payload) {
+                    Ok(event @ MembershipEvent::EndpointJoined { .. }) => {
+                        log::info!("{:?}", event);
+                    }
+                    Ok(event @ MembershipEvent::EndpointLeft { .. }) => {
+                        log::info!("{:?}", event);
+                        left += 1;
+                    }
+                    Err(err) => {
+                        log::error!("recv: {err}");
+                        break;
+                    }
+                }
+            }
+        }
+        Some(name) => {
+            let (_sender, _receiver) = ipmb::join::<(), ()>(
+                ipmb::Options::new("com.ipmb.membership", label!("worker", name), ""),
+                None,
+            )
+            .expect("Join failed");
+
+            thread::sleep(Duration::from_millis(500));
+        }
+    }
+}
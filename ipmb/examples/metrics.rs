@@ -0,0 +1,47 @@
+use ipmb::label;
+use std::{env, process::Command, thread, time::Duration};
+
+fn main() {
+    let mut args = env::args();
+    let command = args.next().unwrap();
+
+    match args.next() {
+        None => {
+            let (sender, mut receiver) = ipmb::join::<String, String>(
+                ipmb::Options::new("com.ipmb.metrics", label!("earth"), ""),
+                None,
+            )
+            .expect("Join failed");
+
+            let mut child = Command::new(command).arg("mars").spawn().unwrap();
+            thread::sleep(Duration::from_millis(200));
+
+            for selector in [
+                ipmb::Selector::multicast(ipmb::LabelOp::parse("earth | mars").unwrap()),
+                ipmb::Selector::unicast("mars"),
+                ipmb::Selector::unicast("venus"),
+            ] {
+                sender
+                    .send(ipmb::Message::new(selector, "hello".to_string()))
+                    .unwrap();
+            }
+            let _ = receiver.recv(Some(Duration::from_secs(1)));
+            thread::sleep(Duration::from_millis(200));
+
+            // The bus controller runs in this process, it counts the routes
+            print!("{}", ipmb::metrics().to_prometheus());
+
+            child.kill().unwrap();
+            child.wait().unwrap();
+        }
+        Some(_) => {
+            let (_sender, mut receiver) = ipmb::join::<(), String>(
+                ipmb::Options::new("com.ipmb.metrics", label!("mars"), ""),
+                None,
+            )
+            .expect("Join failed");
+
+            while receiver.recv(None).is_ok() {}
+        }
+    }
+}
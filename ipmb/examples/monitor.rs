@@ -0,0 +1,70 @@
+use ipmb::{label, Routing, Tap};
+use std::{env, process::Command, thread, time::Duration};
+
+fn main() {
+    let mut builder = env_logger::Builder::new();
+    builder.filter_level(log::LevelFilter::Info);
+    builder.parse_default_env();
+    builder.init();
+
+    let mut args = env::args();
+    let command = args.next().unwrap();
+
+    match args.next() {
+        None => {
+            let mut options = ipmb::Options::new("com.ipmb.monitor", label!("monitor"), "");
+            options.monitor = true;
+
+            let (_sender, mut receiver) =
+                ipmb::join::<(), Tap>(options, None).expect("Join failed");
+
+            let mut child = Command::new(&command).arg("app").spawn().unwrap();
+
+            while let Ok(msg) = receiver.recv(Some(Duration::from_secs(1))) {
+                let tap = msg.payload;
+                let payload = ipmb::decode::<String>(&tap.payload).unwrap_or_default();
+                match tap.routing {
+                    Routing::Routed { receivers } => log::info!(
+                        "{:?} to {}: {payload:?} routed to {receivers:?}",
+                        tap.mode,
+                        tap.label_op
+                    ),
+                    routing => log::info!(
+                        "{:?} to {}: {payload:?} {routing:?}",
+                        tap.mode,
+                        tap.label_op
+                    ),
+                }
+            }
+
+            child.wait().unwrap();
+        }
+        Some(_) => {
+            let (sender, mut receiver) = ipmb::join::<String, String>(
+                ipmb::Options::new("com.ipmb.monitor", label!("app"), ""),
+                None,
+            )
+            .expect("Join failed");
+            thread::sleep(Duration::from_millis(200));
+
+            let send = |selector: ipmb::Selector, payload: &str| {
+                sender
+                    .send(ipmb::Message::new(selector, payload.to_string()))
+                    .unwrap();
+            };
+
+            send(ipmb::Selector::unicast("app"), "to myself");
+            send(ipmb::Selector::unicast("absent"), "nobody");
+            let mut selector = ipmb::Selector::unicast("absent");
+            selector.ttl = Duration::from_millis(100);
+            send(selector, "nobody for a while");
+
+            // The monitor saw the message but didn't take it
+            match receiver.recv(Some(Duration::from_secs(1))) {
+                Ok(msg) => log::info!("app recv: {}", msg.payload),
+                Err(err) => log::error!("app recv: {err}"),
+            }
+            thread::sleep(Duration::from_millis(300));
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use ipmb::label;
+use std::{env, process::Command, thread, time::Duration};
+
+fn main() {
+    let mut builder = env_logger::Builder::new();
+    builder.filter_level(log::LevelFilter::Info);
+    builder.parse_default_env();
+    builder.init();
+
+    let mut args = env::args();
+    let command = args.next().unwrap();
+
+    match args.next() {
+        None => {
+            let (sender, _receiver) = ipmb::join::<String, ()>(
+                ipmb::Options::new("com.ipmb.priority", label!("sensor"), ""),
+                None,
+            )
+            .expect("Join com.ipmb.priority failed");
+
+            let mut child = Command::new(command).arg("ui").spawn().unwrap();
+            thread::sleep(Duration::from_millis(500));
+
+            for i in 0..20 {
+                let msg =
+                    ipmb::Message::new(ipmb::Selector::unicast("ui"), format!("telemetry {i}"));
+                sender.send(msg).unwrap();
+            }
+
+            // Overtakes the telemetry still waiting
+            let mut selector = ipmb::Selector::unicast("ui");
+            selector.priority = ipmb::Priority::High;
+            sender
+                .send(ipmb::Message::new(selector, "input".to_string()))
+                .unwrap();
+
+            child.wait().unwrap();
+        }
+        Some(_) => {
+            let (_sender, mut receiver) = ipmb::join::<(), String>(
+                ipmb::Options::new("com.ipmb.priority", label!("ui"), ""),
+                None,
+            )
+            .expect("Join com.ipmb.priority failed");
+
+            // Busy, messages pile up
+            thread::sleep(Duration::from_secs(1));
+
+            while let Ok(msg) = receiver.recv(Some(Duration::from_secs(2))) {
+                log::info!("recv: {}", msg.payload);
+            }
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use ipmb::label;
+use serde::{Deserialize, Serialize};
+use std::{env, process::Command, thread, time::Duration};
+use type_uuid::TypeUuid;
+
+#[derive(Debug, Serialize, Deserialize, TypeUuid)]
+#[uuid = "9c1d7f0a-2b5e-4c8f-8e3d-6a4b2f1e0d97"]
+struct Frame {
+    name: String,
+    #[serde(with = "serde_bytes")]
+    data: Vec<u8>,
+}
+
+/// Decodes the payload of `Frame` without copying `name` and `data`.
+#[derive(Debug, Deserialize)]
+struct FrameRef<'a> {
+    name: &'a str,
+    data: &'a [u8],
+}
+
+impl TypeUuid for FrameRef<'_> {
+    const UUID: type_uuid::Bytes = Frame::UUID;
+}
+
+fn main() {
+    let mut builder = env_logger::Builder::new();
+    builder.filter_level(log::LevelFilter::Info);
+    builder.parse_default_env();
+    builder.init();
+
+    let mut args = env::args();
+    let command = args.next().unwrap();
+
+    match args.next() {
+        None => {
+            let (_sender, mut receiver) = ipmb::join::<(), Frame>(
+                ipmb::Options::new("com.ipmb.recv_ref", label!("viewer"), ""),
+                None,
+            )
+            .expect("Join com.ipmb.recv_ref failed");
+
+            let mut child = Command::new(command).arg("camera").spawn().unwrap();
+
+            while let Ok(msg) = receiver.recv_ref(Some(Duration::from_secs(2))) {
+                match msg.payload.decode::<FrameRef>() {
+                    Ok(frame) => log::info!("recv: {} ({} bytes)", frame.name, frame.data.len()),
+                    Err(err) => log::error!("decode: {err}"),
+                }
+            }
+
+            child.wait().unwrap();
+        }
+        Some(_) => {
+            let (sender, _receiver) = ipmb::join::<Frame, ()>(
+                ipmb::Options::new("com.ipmb.recv_ref", label!("camera"), ""),
+                None,
+            )
+            .expect("Join com.ipmb.recv_ref failed");
+
+            thread::sleep(Duration::from_millis(500));
+
+            for i in 0..3 {
+                sender
+                    .send(ipmb::Message::new(
+                        ipmb::Selector::unicast("viewer"),
+                        Frame {
+                            name: format!("frame {i}"),
+                            data: vec![0; 1 << 20],
+                        },
+                    ))
+                    .unwrap();
+            }
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use ipmb::label;
+use std::{env, process::Command, thread, time::Duration};
+
+fn main() {
+    let mut builder = env_logger::Builder::new();
+    builder.filter_level(log::LevelFilter::Info);
+    builder.parse_default_env();
+    builder.init();
+
+    let mut args = env::args();
+    let command = args.next().unwrap();
+
+    match args.next() {
+        None => {
+            let (sender, receiver) = ipmb::join::<String, String>(
+                ipmb::Options::new("com.ipmb.request", label!("client"), ""),
+                None,
+            )
+            .expect("Join com.ipmb.request failed");
+
+            let mut child = Command::new(command).arg("server").spawn().unwrap();
+
+            let mut selector = ipmb::Selector::unicast("server");
+            // Wait for the server to join
+            selector.ttl = Duration::from_secs(2);
+
+            for i in 0..5 {
+                // The server is slow to answer the last one
+                let timeout = if i == 4 { 100 } else { 5000 };
+                match sender.request::<String>(
+                    selector.clone(),
+                    format!("ping {i}"),
+                    Some(Duration::from_millis(timeout)),
+                ) {
+                    Ok(reply) => log::info!("reply: {}", reply.payload),
+                    Err(err) => log::error!("request: {err}"),
+                }
+                thread::sleep(Duration::from_millis(500));
+            }
+
+            drop(receiver);
+            child.kill().unwrap();
+            child.wait().unwrap();
+        }
+        Some(_) => {
+            let (sender, mut receiver) = ipmb::join::<String, String>(
+                ipmb::Options::new("com.ipmb.request", label!("server"), ""),
+                None,
+            )
+            .expect("Join com.ipmb.request failed");
+
+            while let Ok(msg) = receiver.recv(None) {
+                if msg.payload == "ping 4" {
+                    thread::sleep(Duration::from_millis(300));
+                }
+
+                if let Some(reply) = msg.reply(msg.payload.replace("ping", "pong")) {
+                    // LateReply for the last one, the client stopped waiting
+                    if let Err(err) = sender.send_reply(reply, Some(Duration::from_secs(1))) {
+                        log::error!("reply: {err}");
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use ipmb::label;
+use std::{env, process::Command, thread, time::Duration};
+
+fn main() {
+    let mut builder = env_logger::Builder::new();
+    builder.filter_level(log::LevelFilter::Info);
+    builder.parse_default_env();
+    builder.init();
+
+    let mut args = env::args();
+    let command = args.next().unwrap();
+
+    match args.next() {
+        None => {
+            let (sender, _receiver) = ipmb::join::<String, ()>(
+                ipmb::Options::new("com.ipmb.confirmed", label!("client"), ""),
+                None,
+            )
+            .expect("Join com.ipmb.confirmed failed");
+
+            let send = |payload: &str| {
+                let msg =
+                    ipmb::Message::new(ipmb::Selector::unicast("server"), payload.to_string());
+                match sender.send_confirmed(msg, Some(Duration::from_secs(1))) {
+                    Ok(delivery) => log::info!("{payload}: {:?}", delivery),
+                    Err(err) => log::error!("{payload}: {err}"),
+                }
+            };
+
+            send("before server");
+
+            let mut child = Command::new(command).arg("server").spawn().unwrap();
+            thread::sleep(Duration::from_secs(1));
+
+            send("after server");
+
+            child.kill().unwrap();
+            child.wait().unwrap();
+        }
+        Some(_) => {
+            let (_sender, mut receiver) = ipmb::join::<(), String>(
+                ipmb::Options::new("com.ipmb.confirmed", label!("server"), ""),
+                None,
+            )
+            .expect("Join com.ipmb.confirmed failed");
+
+            while let Ok(msg) = receiver.recv(None) {
+                log::info!("recv: {}", msg.payload);
+            }
+        }
+    }
+}
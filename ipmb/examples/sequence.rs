@@ -0,0 +1,61 @@
+use ipmb::label;
+use std::{env, num::NonZeroUsize, process::Command, thread, time::Duration};
+
+fn main() {
+    let mut builder = env_logger::Builder::new();
+    builder.filter_level(log::LevelFilter::Info);
+    builder.parse_default_env();
+    builder.init();
+
+    let mut args = env::args();
+    let command = args.next().unwrap();
+
+    match args.next() {
+        None => {
+            let (sender, _receiver) = ipmb::join::<String, ()>(
+                ipmb::Options::new("com.ipmb.sequence", label!("feed"), ""),
+                None,
+            )
+            .expect("Join com.ipmb.sequence failed");
+
+            let mut child = Command::new(command).arg("subscriber").spawn().unwrap();
+            thread::sleep(Duration::from_millis(500));
+
+            for i in 0..200 {
+                let selector = ipmb::Selector::multicast("subscriber");
+                sender
+                    .send(ipmb::Message::new(selector, format!("tick {i}")))
+                    .unwrap();
+            }
+
+            child.wait().unwrap();
+        }
+        Some(_) => {
+            let mut options = ipmb::Options::new("com.ipmb.sequence", label!("subscriber"), "");
+            // Lose messages when falling behind, and find out
+            options.queue_capacity = NonZeroUsize::new(4).unwrap();
+            options.overflow = ipmb::Overflow::DropOldest;
+            options.ordered = true;
+
+            let (_sender, mut receiver) =
+                ipmb::join::<(), String>(options, None).expect("Join com.ipmb.sequence failed");
+
+            // Stalled, the bus controller drops what doesn't fit
+            thread::sleep(Duration::from_secs(1));
+
+            loop {
+                match receiver.recv(Some(Duration::from_secs(2))) {
+                    Ok(msg) => log::info!(
+                        "recv: {} #{}",
+                        msg.payload,
+                        msg.sequence().map(|sequence| sequence.number).unwrap_or(0)
+                    ),
+                    Err(ipmb::RecvError::Gap { sender, from, to }) => {
+                        log::warn!("lost {} messages of {}", to - from, sender)
+                    }
+                    Err(_) => break,
+                }
+            }
+        }
+    }
+}
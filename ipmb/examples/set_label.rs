@@ -0,0 +1,53 @@
+use ipmb::label;
+use std::{env, process::Command, thread, time::Duration};
+
+fn main() {
+    let mut builder = env_logger::Builder::new();
+    builder.filter_level(log::LevelFilter::Info);
+    builder.parse_default_env();
+    builder.init();
+
+    let mut args = env::args();
+    let command = args.next().unwrap();
+
+    match args.next() {
+        None => {
+            let (sender, _receiver) = ipmb::join::<String, ()>(
+                ipmb::Options::new("com.ipmb.set_label", label!("dispatcher"), ""),
+                None,
+            )
+            .expect("Join com.ipmb.set_label failed");
+
+            let mut child = Command::new(command).arg("worker").spawn().unwrap();
+
+            for i in 0..4 {
+                thread::sleep(Duration::from_millis(500));
+
+                let msg = ipmb::Message::new(ipmb::Selector::unicast("busy"), format!("job {i}"));
+                match sender.send_confirmed(msg, Some(Duration::from_secs(1))) {
+                    Ok(delivery) => log::info!("job {i}: {:?}", delivery),
+                    Err(err) => log::error!("job {i}: {err}"),
+                }
+            }
+
+            child.wait().unwrap();
+        }
+        Some(_) => {
+            let (sender, mut receiver) = ipmb::join::<(), String>(
+                ipmb::Options::new("com.ipmb.set_label", label!("worker", "idle"), ""),
+                None,
+            )
+            .expect("Join com.ipmb.set_label failed");
+
+            thread::sleep(Duration::from_millis(750));
+
+            // Take jobs from now on, without rejoining
+            sender.remove_label("idle");
+            sender.insert_label("busy");
+
+            while let Ok(msg) = receiver.recv(Some(Duration::from_secs(2))) {
+                log::info!("recv: {}", msg.payload);
+            }
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use ipmb::{label, MessageBox};
+use serde::{Deserialize, Serialize};
+use std::{env, process::Command, thread, time::Duration};
+use type_uuid::TypeUuid;
+
+#[derive(Debug, Serialize, Deserialize, TypeUuid)]
+#[uuid = "5b4ad5d6-4d0c-4f59-a6e4-0a9b2a7b5f13"]
+struct Frame {
+    seq: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, TypeUuid)]
+#[uuid = "e0a7e5c4-8f4d-4b1e-9a53-31f8e4b1c2d7"]
+enum Control {
+    Pause,
+    Resume,
+}
+
+#[derive(Debug, MessageBox)]
+enum Media {
+    Frame(Frame),
+    Control(Control),
+}
+
+fn main() {
+    let mut builder = env_logger::Builder::new();
+    builder.filter_level(log::LevelFilter::Info);
+    builder.parse_default_env();
+    builder.init();
+
+    let mut args = env::args();
+    let command = args.next().unwrap();
+
+    match args.next() {
+        None => {
+            let (_sender, receiver) = ipmb::join::<(), Media>(
+                ipmb::Options::new("com.ipmb.split", label!("player"), ""),
+                None,
+            )
+            .expect("Join com.ipmb.split failed");
+
+            let mut frames = receiver.split::<Frame>();
+            let mut controls = receiver.split::<Control>();
+
+            let mut child = Command::new(command).arg("source").spawn().unwrap();
+
+            let frame_thread = thread::spawn(move || {
+                while let Ok(msg) = frames.recv(Some(Duration::from_secs(2))) {
+                    log::info!("frame: {}", msg.payload.seq);
+                }
+            });
+            let control_thread = thread::spawn(move || {
+                while let Ok(msg) = controls.recv(Some(Duration::from_secs(2))) {
+                    log::info!("control: {:?}", msg.payload);
+                }
+            });
+
+            frame_thread.join().unwrap();
+            control_thread.join().unwrap();
+            child.wait().unwrap();
+        }
+        Some(_) => {
+            let (sender, _receiver) = ipmb::join::<Media, ()>(
+                ipmb::Options::new("com.ipmb.split", label!("source"), ""),
+                None,
+            )
+            .expect("Join com.ipmb.split failed");
+
+            thread::sleep(Duration::from_millis(500));
+
+            for seq in 0..10 {
+                let payload = match seq {
+                    3 => Media::Control(Control::Pause),
+                    6 => Media::Control(Control::Resume),
+                    _ => Media::Frame(Frame { seq }),
+                };
+                sender
+                    .send(ipmb::Message::new(
+                        ipmb::Selector::unicast("player"),
+                        payload,
+                    ))
+                    .unwrap();
+            }
+        }
+    }
+}
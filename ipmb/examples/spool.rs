@@ -0,0 +1,61 @@
+use ipmb::label;
+use std::{env, fs, path::PathBuf, process::Command, thread, time::Duration};
+
+fn spool_dir() -> PathBuf {
+    env::temp_dir().join("ipmb-spool-example")
+}
+
+fn options(label: ipmb::Label) -> ipmb::Options {
+    let mut options = ipmb::Options::new("com.ipmb.spool", label, "");
+    options.spool = Some(spool_dir());
+    options
+}
+
+fn main() {
+    let mut builder = env_logger::Builder::new();
+    builder.filter_level(log::LevelFilter::Info);
+    builder.parse_default_env();
+    builder.init();
+
+    let mut args = env::args();
+    let command = args.next().unwrap();
+
+    match args.next().as_deref() {
+        None => {
+            let _ = fs::remove_dir_all(spool_dir());
+
+            // Each process is the bus controller while it runs
+            for role in ["sender", "receiver"] {
+                Command::new(&command)
+                    .arg(role)
+                    .spawn()
+                    .unwrap()
+                    .wait()
+                    .unwrap();
+            }
+        }
+        Some("sender") => {
+            let (sender, _receiver) =
+                ipmb::join::<String, ()>(options(label!("sender")), None).expect("Join failed");
+
+            let mut selector = ipmb::Selector::unicast("receiver");
+            selector.ttl = Duration::from_secs(60);
+            sender
+                .send(ipmb::Message::new(selector, "hello".to_string()))
+                .unwrap();
+            log::info!("sent while no receiver is on the bus");
+
+            // Let the bus controller buffer it before exiting
+            thread::sleep(Duration::from_millis(200));
+        }
+        Some(_) => {
+            let (_sender, mut receiver) =
+                ipmb::join::<(), String>(options(label!("receiver")), None).expect("Join failed");
+
+            match receiver.recv(Some(Duration::from_secs(2))) {
+                Ok(msg) => log::info!("recv: {}", msg.payload),
+                Err(err) => log::error!("recv: {err}"),
+            }
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use ipmb::label;
+use std::{env, process::Command, time::Duration};
+
+fn join(name: &'static str) -> (ipmb::EndpointSender<String>, ipmb::EndpointReceiver<String>) {
+    ipmb::join::<String, String>(ipmb::Options::new("com.ipmb.trace", label!(name), ""), None)
+        .expect("Join failed")
+}
+
+fn main() {
+    // Spans are printed with the trace id, the same in every process
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    let mut args = env::args();
+    let command = args.next().unwrap();
+
+    match args.next().as_deref() {
+        None => {
+            let (sender, _receiver) = join("browser");
+
+            let mut children: Vec<_> = ["codec", "renderer"]
+                .into_iter()
+                .map(|name| Command::new(&command).arg(name).spawn().unwrap())
+                .collect();
+
+            let mut selector = ipmb::Selector::unicast("renderer");
+            selector.ttl = Duration::from_secs(2);
+
+            let _trace = ipmb::TraceContext::new().enter();
+            tracing::info!("browser: {}", ipmb::TraceContext::current().unwrap());
+            sender
+                .send(ipmb::Message::new(selector, "navigate".to_string()))
+                .unwrap();
+
+            for child in &mut children {
+                child.wait().unwrap();
+            }
+        }
+        Some("renderer") => {
+            let (sender, mut receiver) = join("renderer");
+            let msg = receiver.recv(Some(Duration::from_secs(2))).unwrap();
+
+            // Sent while the trace of the message is entered, so it continues it
+            let _trace = msg.enter_trace();
+            tracing::info!("renderer: {} {}", msg.payload, msg.trace().unwrap());
+
+            let mut selector = ipmb::Selector::unicast("codec");
+            selector.ttl = Duration::from_secs(2);
+            sender
+                .send(ipmb::Message::new(selector, "decode".to_string()))
+                .unwrap();
+        }
+        Some(_) => {
+            let (_sender, mut receiver) = join("codec");
+            let msg = receiver.recv(Some(Duration::from_secs(2))).unwrap();
+
+            let _trace = msg.enter_trace();
+            tracing::info!("codec: {} {}", msg.payload, msg.trace().unwrap());
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use crate::{
+    platform::{linux::Fd, EncodedMessage},
+    rejoin, EndpointReceiver, EndpointSender, Error, IoMultiplexing, JoinError, Message,
+    MessageBox, RecvError, Rule, SendError,
+};
+use futures_core::Stream;
+use std::{
+    future::{self, Future},
+    io,
+    pin::Pin,
+    sync::{mpsc::TrySendError, Arc},
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{unix::AsyncFd, Interest},
+    task::JoinHandle,
+};
+
+/// Where an async receiver waits for messages, follows the rule across rejoins.
+#[derive(Default)]
+pub(crate) struct Readiness {
+    source: Option<(Arc<IoMultiplexing>, AsyncFd<Fd>)>,
+    rejoin: Option<JoinHandle<Result<(), JoinError>>>,
+}
+
+impl Readiness {
+    fn poll_ready(&mut self, im: Arc<IoMultiplexing>, cx: &mut Context<'_>) -> Poll<()> {
+        if !matches!(&self.source, Some((source, _)) if Arc::ptr_eq(source, &im)) {
+            // Register a duplicate, the same epoll fd may be watched by another receiver
+            match im
+                .fd
+                .clone()
+                .and_then(|fd| AsyncFd::with_interest(fd, Interest::READABLE))
+            {
+                Ok(fd) => self.source = Some((im, fd)),
+                Err(err) => {
+                    log::error!("async fd: {}", err);
+                    cx.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
+            }
+        }
+
+        let (im, fd) = self.source.as_ref().unwrap();
+        match ready!(fd.poll_read_ready(cx)) {
+            Ok(mut guard) => {
+                guard.clear_ready();
+                im.try_clear_waker();
+            }
+            Err(err) => {
+                log::error!("async fd: {}", err);
+                self.source = None;
+            }
+        }
+
+        Poll::Ready(())
+    }
+}
+
+impl<R: MessageBox> EndpointReceiver<R> {
+    /// Asynchronous version of [`recv`](EndpointReceiver::recv), waits without blocking the thread.
+    pub async fn recv_async(&mut self) -> Result<Message<R>, RecvError> {
+        future::poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Result<Message<R>, RecvError>> {
+        loop {
+            if let Some(rejoin) = &mut self.readiness.rejoin {
+                let r = ready!(Pin::new(rejoin).poll(cx));
+                self.readiness.rejoin = None;
+                r.expect("rejoin panicked")?;
+            }
+
+            match self.try_recv() {
+                Ok(Some(msg)) => return Poll::Ready(Ok(msg)),
+                Ok(None) => {}
+                Err(err) => return Poll::Ready(Err(err)),
+            }
+
+            let im = match &*self.rule.read().unwrap() {
+                // The bus could not be rejoined immediately, keep trying in background
+                Rule::Client {
+                    io_hub: None,
+                    reader_closed: false,
+                    epoch,
+                    ..
+                } => {
+                    let rule = self.rule.clone();
+                    let inbox = self.inbox.clone();
+                    let epoch = *epoch;
+                    self.readiness.rejoin = Some(tokio::task::spawn_blocking(move || {
+                        rejoin(&rule, &inbox, epoch, None)
+                    }));
+                    continue;
+                }
+                Rule::Client { im, .. } => im.clone(),
+                Rule::Server { notify, .. } => notify.clone(),
+            };
+
+            // Messages may also be dispatched by a thread blocking in `recv` or `request`
+            self.inbox.register(cx.waker());
+
+            ready!(self.readiness.poll_ready(im, cx));
+        }
+    }
+}
+
+impl<R> Unpin for EndpointReceiver<R> {}
+
+impl<R: MessageBox> Stream for EndpointReceiver<R> {
+    type Item = Result<Message<R>, RecvError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_recv(cx).map(Some)
+    }
+}
+
+enum Pending {
+    Writable(io::Result<Fd>),
+    Rejoin(u32),
+}
+
+impl<T: MessageBox> EndpointSender<T> {
+    /// Asynchronous version of [`send`](EndpointSender::send), waits for the kernel buffer
+    /// without blocking the thread.
+    ///
+    /// The message is numbered before it's sent, so a failed send leaves a gap in its
+    /// [`Sequence`](crate::Sequence), and concurrent sends may arrive in a different order.
+    pub async fn send_async(&self, msg: Message<T>) -> Result<(), SendError> {
+        let msg = {
+            let mut sequencer = self.sequencer.lock().unwrap();
+            let msg = self.encode(msg, &sequencer)?;
+            sequencer.advance(msg.selector.sequence);
+            msg
+        };
+        #[cfg(feature = "tracing")]
+        let span = crate::trace::send_span(&msg.selector);
+
+        let send = self.send_encoded_async(msg);
+        // Entered whenever the future is polled, on whichever thread
+        #[cfg(feature = "tracing")]
+        let send = tracing::Instrument::instrument(send, span);
+        send.await
+    }
+
+    async fn send_encoded_async(&self, mut msg: EncodedMessage) -> Result<(), SendError> {
+        let (bus_sender, im) = loop {
+            let pending = match &*self.rule.read().unwrap() {
+                Rule::Client { remote, epoch, .. } => {
+                    match msg.send_timeout(remote, Duration::ZERO) {
+                        Ok(_) => return Ok(()),
+                        Err(Error::Timeout) => Pending::Writable(remote.lock().clone()),
+                        Err(Error::Disconnect) => Pending::Rejoin(*epoch),
+                        Err(_) => unreachable!(),
+                    }
+                }
+                Rule::Server { bus_sender, im, .. } => {
+                    break (bus_sender.lock().unwrap().clone(), im.clone());
+                }
+            };
+
+            match pending {
+                Pending::Writable(fd) => {
+                    match fd.and_then(|fd| AsyncFd::with_interest(fd, Interest::WRITABLE)) {
+                        Ok(fd) => {
+                            if let Ok(mut guard) = fd.writable().await {
+                                guard.clear_ready();
+                            }
+                        }
+                        Err(err) => {
+                            log::error!("async fd: {}", err);
+                            tokio::task::yield_now().await;
+                        }
+                    }
+                }
+                Pending::Rejoin(epoch) => {
+                    let rule = self.rule.clone();
+                    let inbox = self.inbox.clone();
+                    tokio::task::spawn_blocking(move || rejoin(&rule, &inbox, epoch, None))
+                        .await
+                        .expect("rejoin panicked")?;
+                }
+            }
+        };
+
+        // The bus controller is in this process, wait for room in its channel off the runtime
+        match bus_sender.try_send(msg) {
+            Ok(_) => {}
+            Err(TrySendError::Full(msg)) => {
+                tokio::task::spawn_blocking(move || bus_sender.send(msg).unwrap())
+                    .await
+                    .expect("send panicked");
+            }
+            Err(TrySendError::Disconnected(_)) => unreachable!(),
+        }
+        im.wake();
+        Ok(())
+    }
+}
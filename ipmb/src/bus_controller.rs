@@ -1,29 +1,78 @@
+#[cfg(feature = "async")]
+use crate::IoMultiplexing;
 use crate::{
     decode,
-    message::{ConnectMessage, ConnectMessageAck},
+    message::{AckMessage, ConfigureMessage, ConnectMessage, ConnectMessageAck, QueryMessage},
+    metrics::{self, COUNTERS},
     platform::IoHub,
-    version, EncodedMessage, EndpointID, Error, Label, LabelOp, Message, Remote, Selector,
-    SelectorMode,
+    priority::PriorityQueue,
+    spool::Spool,
+    version, BusInfo, Correlation, Delivery, EncodedMessage, EndpointID, EndpointInfo, Error,
+    Label, LabelOp, LeaveReason, MembershipEvent, MemoryRegion, Message, MessageBox, Object,
+    Overflow, Remote, Routing, Selector, SelectorMode, Sequence, Tap,
 };
 use std::{
+    collections::VecDeque,
     mem,
-    sync::mpsc::Sender,
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{Receiver, SyncSender, TrySendError},
+        Arc, Mutex, Weak,
+    },
     thread,
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime},
 };
-use type_uuid::TypeUuid;
+use type_uuid::{Bytes, TypeUuid};
+
+const DETECT_REACHABLE_INTERVAL: Duration = Duration::from_secs(30);
+/// Platforms don't tell when an endpoint can take messages again, queues are retried at this interval.
+const FLUSH_INTERVAL: Duration = Duration::from_millis(10);
+const MESSAGE_BUFFER_CAPACITY: usize = 4096;
+/// The most messages read at once, so they are handled by priority.
+const RECV_BATCH: usize = 64;
+/// The most reliable messages kept until they are acked, the oldest is forgotten beyond.
+const UNACKED_CAPACITY: usize = 4096;
 
 pub struct BusController {
     label: Label,
     token: String,
-    #[allow(dead_code)]
     endpoint_id: EndpointID,
-    sender: Sender<EncodedMessage>,
+    sender: SyncSender<EncodedMessage>,
+    /// Lets the oldest message be dropped when the channel to the endpoint in this process is full.
+    receiver: Weak<Mutex<Receiver<EncodedMessage>>>,
+    /// Messages in the channel to the endpoint in this process, its reader counts them down.
+    depth: Arc<AtomicUsize>,
+    #[cfg(feature = "async")]
+    notify: Option<Arc<IoMultiplexing>>,
+    /// Membership subscription of the endpoint in this process.
+    membership: Option<LabelOp>,
+    /// Whether the endpoint in this process is a monitor, see `Options::monitor`.
+    monitor: bool,
+    /// Overflow policy of the endpoint in this process, the channel is bounded by its queue capacity.
+    overflow: Overflow,
     endpoints: Vec<Endpoint>,
     message_buffer: Vec<(Instant, EncodedMessage)>,
     message_buffer_swap: Vec<(Instant, EncodedMessage)>,
+    /// Keeps `message_buffer` on disk, see `Options::spool`.
+    spool: Option<Spool>,
+    /// Messages were reloaded from the spool, they may be routed to the endpoint in this process.
+    reloaded: bool,
+    /// Messages read but not yet handled.
+    incoming: PriorityQueue,
+    /// Control messages read but not yet handled, they are handled in arrival order ahead of `incoming`.
+    control: VecDeque<EncodedMessage>,
+    /// Messages dropped because their deadline passed.
+    expired: u64,
+    /// Copies of reliable messages delivered but not yet acked, by receiving endpoint.
+    unacked: VecDeque<(EndpointID, EncodedMessage)>,
+    /// Reliable messages of endpoints that left without acking them, to be routed again.
+    redeliveries: Vec<EncodedMessage>,
+    /// Senders whose messages filled the queue of an endpoint with `Overflow::Block`.
+    holds: Vec<Hold>,
     io_hub: IoHub,
     last_detect_reachable: Instant,
+    started: SystemTime,
 }
 
 impl BusController {
@@ -31,7 +80,9 @@ impl BusController {
         endpoint_id: EndpointID,
         label: Label,
         token: String,
-        sender: Sender<EncodedMessage>,
+        sender: SyncSender<EncodedMessage>,
+        receiver: Weak<Mutex<Receiver<EncodedMessage>>>,
+        depth: Arc<AtomicUsize>,
         io_hub: IoHub,
     ) -> Self {
         Self {
@@ -39,20 +90,67 @@ impl BusController {
             label,
             token,
             sender,
+            receiver,
+            depth,
+            #[cfg(feature = "async")]
+            notify: None,
+            membership: None,
+            monitor: false,
+            overflow: Overflow::Block,
             endpoints: Default::default(),
             message_buffer: Default::default(),
             message_buffer_swap: Default::default(),
+            spool: None,
+            reloaded: false,
+            incoming: Default::default(),
+            control: Default::default(),
+            expired: 0,
+            unacked: Default::default(),
+            redeliveries: Default::default(),
+            holds: Default::default(),
             io_hub,
             last_detect_reachable: Instant::now(),
+            started: SystemTime::now(),
         }
     }
 
+    /// Wake `notify` whenever a message is delivered to the endpoint in this process.
+    #[cfg(feature = "async")]
+    pub(crate) fn with_notify(mut self, notify: Arc<IoMultiplexing>) -> Self {
+        self.notify = Some(notify);
+        self
+    }
+
+    /// Keep buffered messages in `dir`, and take over those a previous bus controller left there.
+    pub(crate) fn with_spool(mut self, dir: &Path, identifier: &str) -> Self {
+        match Spool::open(dir, identifier) {
+            Ok((spool, messages)) => {
+                if !messages.is_empty() {
+                    log::info!("reload {} spooled messages", messages.len());
+                }
+                self.reloaded = !messages.is_empty();
+                self.message_buffer = messages;
+                self.spool = Some(spool);
+            }
+            Err(err) => log::error!("spool: {}", err),
+        }
+        self
+    }
+
     pub fn run(mut self) {
         thread::Builder::new()
             .name(String::from("ipmb bus controller"))
             .spawn(move || loop {
-                let msg = match self.io_hub.recv(None, None) {
+                let msg = match self.recv() {
                     Ok(msg) => msg,
+                    Err(Error::Timeout) => {
+                        let now = Instant::now();
+                        self.flush_queues();
+                        self.detect_reachable(now);
+                        self.redeliver(now);
+                        self.maintain(now);
+                        continue;
+                    }
                     Err(Error::VersionMismatch(_, Some(remote))) => {
                         let _ = Message::new(
                             Selector::unicast(LabelOp::True),
@@ -62,26 +160,107 @@ impl BusController {
                         .send(&remote);
                         continue;
                     }
+                    Err(Error::Disconnect) => {
+                        // Acks read before the connection was closed still count
+                        let (acks, control) = mem::take(&mut self.control)
+                            .into_iter()
+                            .partition::<Vec<_>, _>(|msg| {
+                                msg.selector.uuid == <AckMessage as TypeUuid>::UUID
+                            });
+                        self.control = control.into();
+                        for msg in acks {
+                            self.ack(msg);
+                        }
+
+                        // The connection of an endpoint was closed, find out which one
+                        self.retain_endpoints(LeaveReason::Disconnect, |ep| !ep.remote.is_dead());
+                        self.redeliver(Instant::now());
+                        continue;
+                    }
                     _ => continue,
                 };
 
                 let now = Instant::now();
 
-                let (remain, endpoint_connected) = self.handle_message(msg);
+                let Some(mut msg) = self.park(msg) else {
+                    continue;
+                };
 
-                if let Some(remain) = remain {
-                    if !remain.selector.ttl.is_zero() {
-                        self.message_buffer
-                            .push((now + remain.selector.ttl, remain));
+                let confirm = match msg.selector.correlation {
+                    Some(Correlation::Confirm(id)) => {
+                        msg.selector.source.map(|source| (source, id))
+                    }
+                    Some(Correlation::ConfirmReply { reply, confirm }) => {
+                        msg.selector.correlation = Some(Correlation::Reply(reply));
+                        msg.selector.source.map(|source| (source, confirm))
                     }
-                } else if endpoint_connected && !self.message_buffer.is_empty() {
+                    _ => None,
+                };
+
+                let tap = self.tap(&msg);
+                let expired = msg.selector.is_expired();
+                let unicast =
+                    msg.selector.mode == SelectorMode::Unicast && !is_control(msg.selector.uuid);
+                let source = msg
+                    .selector
+                    .source
+                    .filter(|_| !is_control(msg.selector.uuid));
+                let (remain, receivers, mut routes_changed) = self.handle_message(msg);
+                routes_changed |= mem::take(&mut self.reloaded);
+
+                if let Some(source) = source {
+                    self.hold(source, &receivers);
+                }
+
+                if unicast && !expired && receivers.is_empty() {
+                    metrics::increment(&COUNTERS.unicast_misses);
+                }
+
+                if let Some((source, id)) = confirm {
+                    let delivery = match &remain {
+                        _ if !receivers.is_empty() => Delivery::Routed {
+                            receivers: receivers.len(),
+                        },
+                        Some(remain) if !remain.selector.ttl.is_zero() => Delivery::Buffered {
+                            ttl: remain.selector.ttl,
+                        },
+                        _ => Delivery::NoRoute,
+                    };
+
+                    let mut msg = Message::new(Selector::to_endpoint(source), delivery);
+                    msg.selector.correlation = Some(Correlation::Reply(id));
+                    self.send_to(msg);
+                }
+
+                self.publish_tap(tap, routing(expired, remain.as_ref(), receivers));
+
+                if let Some(remain) = remain {
+                    self.buffer(now, remain);
+                } else if routes_changed && !self.message_buffer.is_empty() {
                     let mut message_buffer = mem::take(&mut self.message_buffer);
+                    // Stable, the order of the same priority is kept
+                    message_buffer.sort_by_key(|(_, msg)| std::cmp::Reverse(msg.selector.priority));
 
                     for (expire, msg) in message_buffer.drain(..) {
-                        let (remain, _) = self.handle_message(msg);
-                        if let Some(remain) = remain {
-                            if expire > now {
-                                self.message_buffer_swap.push((expire, remain));
+                        let key = Spool::key(&msg);
+                        let tap = self.tap(&msg);
+                        // Counted by `handle_message` when it drops the message
+                        let deadline_passed = msg.selector.is_expired();
+                        match self.handle_message(msg) {
+                            (Some(remain), _, _) if expire > now => {
+                                self.message_buffer_swap.push((expire, remain))
+                            }
+                            (_, receivers, _) => {
+                                self.unspool(key);
+                                let routing = if receivers.is_empty() {
+                                    if !deadline_passed {
+                                        metrics::increment(&COUNTERS.expired);
+                                    }
+                                    Routing::Expired
+                                } else {
+                                    Routing::Routed { receivers }
+                                };
+                                self.publish_tap(tap, routing);
                             }
                         }
                     }
@@ -90,25 +269,187 @@ impl BusController {
                     mem::swap(&mut self.message_buffer, &mut self.message_buffer_swap);
                 }
 
+                self.flush_queues();
                 self.detect_reachable(now);
+                self.redeliver(now);
                 self.maintain(now);
             })
             .expect("failed to spawn ipmb bus controller");
     }
 
-    // Don't read or write self.message_buffer
+    /// Keep a message that could not be routed for its ttl.
+    fn buffer(&mut self, now: Instant, msg: EncodedMessage) {
+        if msg.selector.ttl.is_zero() {
+            return;
+        }
+        metrics::increment(&COUNTERS.buffered);
+
+        if self.message_buffer.len() >= MESSAGE_BUFFER_CAPACITY {
+            log::warn!("message buffer full, drop the oldest message");
+            let lowest = self
+                .message_buffer
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, (_, msg))| msg.selector.priority)
+                .map(|(i, _)| i)
+                .unwrap();
+            let (_, dropped) = self.message_buffer.remove(lowest);
+            self.unspool(Spool::key(&dropped));
+            self.publish_tap(self.tap(&dropped), Routing::Evicted);
+        }
+
+        let expire = now + msg.selector.ttl;
+        if let Some(spool) = &mut self.spool {
+            spool.add(expire, &msg);
+        }
+        self.message_buffer.push((expire, msg));
+    }
+
+    /// A buffered message with spool `key` was routed or dropped.
+    fn unspool(&mut self, key: Option<Sequence>) {
+        if let Some(spool) = &mut self.spool {
+            spool.remove(key);
+        }
+    }
+
+    /// Route the reliable messages of endpoints that left again, each to one endpoint.
+    /// Those sent to the endpoint that left with `Selector::to_endpoint` are dropped.
+    fn redeliver(&mut self, now: Instant) {
+        // Routing may find more endpoints gone
+        while !self.redeliveries.is_empty() {
+            for mut msg in mem::take(&mut self.redeliveries) {
+                if msg.selector.target.is_some() {
+                    // Endpoint ids are never reused, the endpoint it was sent to is gone for good
+                    log::warn!(
+                        "target of {:?} left, it's not redelivered",
+                        msg.selector.sequence
+                    );
+                    metrics::increment(&COUNTERS.unacked_dropped);
+                    self.publish_tap(self.tap(&msg), Routing::NoRoute);
+                    continue;
+                }
+
+                msg.selector.redelivered += 1;
+                msg.selector.mode = SelectorMode::Unicast;
+                // The selector is sent as encoded
+                let msg = match duplicate(&msg) {
+                    Ok(msg) => msg,
+                    Err(err) => {
+                        log::error!("redeliver: {:?}", err);
+                        continue;
+                    }
+                };
+
+                let tap = self.tap(&msg);
+                let expired = msg.selector.is_expired();
+                let (remain, receivers, _) = self.handle_message(msg);
+                self.publish_tap(tap, routing(expired, remain.as_ref(), receivers));
+                if let Some(remain) = remain {
+                    if remain.selector.ttl.is_zero() {
+                        log::warn!("no endpoint to redeliver to, drop the message");
+                    }
+                    self.buffer(now, remain);
+                }
+            }
+        }
+    }
+
+    /// Keep `copy` of a reliable message delivered to `receivers` until each acks it.
+    fn track(&mut self, receivers: &[EndpointID], copy: Option<EncodedMessage>) {
+        let (Some(copy), Some((last, rest))) = (copy, receivers.split_last()) else {
+            return;
+        };
+
+        for receiver in rest {
+            match duplicate(&copy) {
+                Ok(msg) => self.unacked_push(*receiver, msg),
+                Err(err) => log::error!("track: {:?}", err),
+            }
+        }
+        self.unacked_push(*last, copy);
+    }
+
+    fn unacked_push(&mut self, receiver: EndpointID, msg: EncodedMessage) {
+        if self.unacked.len() >= UNACKED_CAPACITY {
+            if let Some((receiver, msg)) = self.unacked.pop_front() {
+                metrics::increment(&COUNTERS.unacked_dropped);
+                log::warn!(
+                    "too many unacked messages, forget {:?} of {}, it's not redelivered",
+                    msg.selector.sequence,
+                    receiver
+                );
+            }
+        }
+        self.unacked.push_back((receiver, msg));
+    }
+
+    fn ack(&mut self, encoded_msg: EncodedMessage) {
+        let Some(source) = encoded_msg.selector.source else {
+            return;
+        };
+        let Ok(payload) = decode::<AckMessage>(encoded_msg.payload_data) else {
+            return;
+        };
+
+        self.unacked.retain(|(receiver, msg)| {
+            *receiver != source || msg.selector.sequence != Some(payload.sequence)
+        });
+    }
+
+    /// Read the messages that have arrived and take the one to handle next, control messages
+    /// first in the order they arrived, then by priority.
+    fn recv(&mut self) -> Result<EncodedMessage, Error> {
+        let mut timeout = if self.incoming.is_empty() && self.control.is_empty() {
+            self.recv_timeout()
+        } else {
+            Some(Duration::ZERO)
+        };
+
+        while self.incoming.len() + self.control.len() < RECV_BATCH {
+            match self.io_hub.recv(timeout, None) {
+                Ok(msg) if is_control(msg.selector.uuid) => self.control.push_back(msg),
+                Ok(msg) => self.incoming.push_back(msg),
+                Err(Error::Timeout) => break,
+                Err(err) => return Err(err),
+            }
+            timeout = Some(Duration::ZERO);
+        }
+
+        self.control
+            .pop_front()
+            .or_else(|| self.incoming.pop_front())
+            .ok_or(Error::Timeout)
+    }
+
+    /// Returns the message if it could not be routed, the endpoints it was routed to
+    /// and whether an endpoint connected or changed its label.
+    // Don't write self.message_buffer, it's taken while buffered messages are routed again
     fn handle_message(
         &mut self,
         mut encoded_msg: EncodedMessage,
-    ) -> (Option<EncodedMessage>, bool) {
-        let mut routed = false;
+    ) -> (Option<EncodedMessage>, Vec<EndpointID>, bool) {
+        let mut delivered = Vec::new();
         let mut remain = None;
-        let mut endpoint_connected = false;
+        let mut routes_changed = false;
+        let size = encoded_msg.payload_data.len();
+
+        if encoded_msg.selector.is_expired() {
+            self.expired += 1;
+            metrics::increment(&COUNTERS.expired);
+            log::debug!("drop expired message, {} so far", self.expired);
+            return (None, delivered, routes_changed);
+        }
 
         match encoded_msg.selector.uuid {
             <ConnectMessage as TypeUuid>::UUID => {
-                endpoint_connected = self.endpoint_connect(encoded_msg);
+                routes_changed = self.endpoint_connect(encoded_msg);
             }
+            <ConfigureMessage as TypeUuid>::UUID => {
+                // Buffered messages may match the new label
+                routes_changed = self.configure(encoded_msg);
+            }
+            <AckMessage as TypeUuid>::UUID => self.ack(encoded_msg),
+            <QueryMessage as TypeUuid>::UUID => self.answer_query(encoded_msg),
             #[cfg(windows)]
             <crate::message::FetchProcessHandleMessage as TypeUuid>::UUID => {
                 if let Err(err) =
@@ -117,45 +458,147 @@ impl BusController {
                     log::error!("{}", err);
                 }
             }
-            _ => {
-                self.endpoints.retain(|Endpoint { label, remote, .. }| {
-                    let mut online = true;
+            _ if encoded_msg.selector.target.is_some() => {
+                // Endpoint ids are never reused, so targeted messages are not buffered
+                let target = encoded_msg.selector.target.unwrap();
 
-                    if routed && encoded_msg.selector.mode == SelectorMode::Unicast {
-                        return online;
-                    }
+                let copy = reliable_copy(&encoded_msg);
 
-                    if encoded_msg.selector.label_op.validate(label) {
-                        match encoded_msg.send(remote) {
-                            Ok(_) => routed = true,
-                            Err(Error::Disconnect) => online = false,
-                            _ => {}
+                if target == self.endpoint_id {
+                    if self.send_local(encoded_msg).is_none() {
+                        delivered.push(target);
+                        COUNTERS.route(target, &self.label, size);
+                        self.track(&[target], copy);
+                    }
+                } else if let Some(ep) = self.endpoints.iter_mut().find(|ep| ep.id == target) {
+                    match ep.queue.send(&ep.remote, &mut encoded_msg) {
+                        Ok(_) => {
+                            delivered.push(target);
+                            COUNTERS.route(target, &ep.label, size);
+                            self.track(&[target], copy);
                         }
+                        Err(Error::Disconnect) => {
+                            self.retain_endpoints(LeaveReason::Disconnect, |ep| ep.id != target);
+                        }
+                        Err(_) => {}
                     }
+                } else {
+                    log::debug!("target endpoint not found: {:?}", target);
+                }
+            }
+            _ => {
+                let copy = reliable_copy(&encoded_msg);
+
+                self.retain_endpoints(
+                    LeaveReason::Disconnect,
+                    |Endpoint {
+                         id,
+                         label,
+                         remote,
+                         queue,
+                         monitor,
+                         ..
+                     }| {
+                        let mut online = true;
 
-                    online
-                });
+                        // Monitors see messages as taps, they are never receivers
+                        if *monitor
+                            || (!delivered.is_empty()
+                                && encoded_msg.selector.mode == SelectorMode::Unicast)
+                        {
+                            return online;
+                        }
+
+                        if encoded_msg.selector.label_op.validate(label) {
+                            match queue.send(remote, &mut encoded_msg) {
+                                Ok(_) => {
+                                    delivered.push(*id);
+                                    COUNTERS.route(*id, label, size);
+                                }
+                                Err(Error::Disconnect) => online = false,
+                                _ => {}
+                            }
+                        }
 
-                if (!routed || encoded_msg.selector.mode == SelectorMode::Multicast)
+                        online
+                    },
+                );
+
+                if !self.monitor
+                    && (delivered.is_empty()
+                        || encoded_msg.selector.mode == SelectorMode::Multicast)
                     && encoded_msg.selector.label_op.validate(&self.label)
                 {
-                    match self.sender.send(encoded_msg) {
-                        Ok(_) => {}
-                        Err(err) => {
-                            if !routed {
-                                remain = Some(err.0);
+                    match self.send_local(encoded_msg) {
+                        None => {
+                            delivered.push(self.endpoint_id);
+                            COUNTERS.route(self.endpoint_id, &self.label, size);
+                        }
+                        Some(encoded_msg) => {
+                            if delivered.is_empty() {
+                                remain = Some(encoded_msg);
                             }
                         }
                     }
                 } else {
-                    if !routed {
+                    if delivered.is_empty() {
                         remain = Some(encoded_msg);
                     }
                 }
+
+                self.track(&delivered, copy);
+            }
+        }
+
+        (remain, delivered, routes_changed)
+    }
+
+    /// Deliver to the endpoint in this process, returns the message if it was not delivered.
+    ///
+    /// Never blocks, waiting for a reader of this process would hold up the whole bus,
+    /// so a full channel drops the message for `Overflow::Block` too.
+    fn send_local(&self, mut encoded_msg: EncodedMessage) -> Option<EncodedMessage> {
+        loop {
+            // Counted before sending, the reader may take the message before `try_send` returns
+            self.depth.fetch_add(1, Ordering::Relaxed);
+            let r = self.sender.try_send(encoded_msg);
+            if r.is_err() {
+                self.depth.fetch_sub(1, Ordering::Relaxed);
+            }
+
+            match r {
+                Ok(_) => break,
+                Err(TrySendError::Full(msg)) if self.overflow == Overflow::DropOldest => {
+                    // The channel doesn't stay full while the receiver is locked by a reader
+                    let receiver = self.receiver.upgrade()?;
+                    let dropped = match receiver.try_lock() {
+                        Ok(receiver) => receiver.try_recv().is_ok(),
+                        Err(_) => false,
+                    };
+                    if !dropped {
+                        return Some(msg);
+                    }
+                    self.depth.fetch_sub(1, Ordering::Relaxed);
+                    encoded_msg = msg;
+                }
+                Err(TrySendError::Full(msg)) => {
+                    if self.overflow == Overflow::Block {
+                        log::warn!(
+                            "queue of the endpoint in this process is full, drop the message"
+                        );
+                    }
+                    return Some(msg);
+                }
+                Err(TrySendError::Disconnected(msg)) => return Some(msg),
             }
         }
 
-        (remain, endpoint_connected)
+        #[cfg(feature = "async")]
+        if let Some(notify) = &self.notify {
+            notify.wake();
+        }
+
+        None
     }
 
     fn endpoint_connect(&mut self, mut encoded_msg: EncodedMessage) -> bool {
@@ -213,7 +656,12 @@ impl BusController {
         let pair = Endpoint {
             id: endpoint_id,
             label: payload.label,
+            credentials: remote.peer_credentials(),
             remote,
+            membership: None,
+            monitor: false,
+            queue: Queue::new(),
+            connected_at: SystemTime::now(),
         };
 
         if self
@@ -224,26 +672,560 @@ impl BusController {
             return false;
         }
 
+        let (id, label) = (pair.id, pair.label.clone());
         let _ = self.endpoints.push(pair);
+        self.publish(MembershipEvent::EndpointJoined { id, label });
         true
     }
 
+    /// Returns whether the label of the endpoint changed.
+    fn configure(&mut self, encoded_msg: EncodedMessage) -> bool {
+        let Some(source) = encoded_msg.selector.source else {
+            return false;
+        };
+        let Ok(payload) = decode::<ConfigureMessage>(encoded_msg.payload_data) else {
+            return false;
+        };
+
+        let (label, membership) = if source == self.endpoint_id {
+            self.overflow = payload.overflow;
+            self.monitor = payload.monitor;
+            (&mut self.label, &mut self.membership)
+        } else if let Some(ep) = self.endpoints.iter_mut().find(|ep| ep.id == source) {
+            ep.queue.capacity = payload.queue_capacity.get();
+            ep.queue.overflow = payload.overflow;
+            ep.monitor = payload.monitor;
+            (&mut ep.label, &mut ep.membership)
+        } else {
+            return false;
+        };
+
+        let old_label = (*label != payload.label).then(|| mem::replace(label, payload.label));
+        let subscribed = *membership != payload.membership;
+        *membership = payload.membership.clone();
+
+        if let Some(old_label) = &old_label {
+            self.publish_relabel(source, old_label);
+        }
+
+        match payload.membership {
+            Some(label_op) if subscribed => {
+                // Report the endpoints already on the bus
+                let members = std::iter::once((self.endpoint_id, &self.label))
+                    .chain(self.endpoints.iter().map(|ep| (ep.id, &ep.label)))
+                    .filter(|(id, label)| *id != source && label_op.validate(label))
+                    .map(|(id, label)| MembershipEvent::EndpointJoined {
+                        id,
+                        label: label.clone(),
+                    })
+                    .collect::<Vec<_>>();
+
+                for event in members {
+                    self.send_to(Message::new(Selector::to_endpoint(source), event));
+                }
+            }
+            _ => {}
+        }
+
+        old_label.is_some()
+    }
+
+    /// Reply with a [`BusInfo`] to a query, which is never buffered, so `message_buffer` is complete.
+    fn answer_query(&mut self, encoded_msg: EncodedMessage) {
+        let (Some(source), Some(Correlation::Request(id))) = (
+            encoded_msg.selector.source,
+            encoded_msg.selector.correlation,
+        ) else {
+            return;
+        };
+
+        #[cfg(unix)]
+        let uid = Some(unsafe { libc::getuid() });
+        #[cfg(not(unix))]
+        let uid: Option<u32> = None;
+
+        let mut buffered: Vec<(LabelOp, usize)> = Vec::new();
+        for (_, msg) in &self.message_buffer {
+            match buffered
+                .iter_mut()
+                .find(|(label_op, _)| *label_op == msg.selector.label_op)
+            {
+                Some((_, count)) => *count += 1,
+                None => buffered.push((msg.selector.label_op.clone(), 1)),
+            }
+        }
+
+        let info = BusInfo {
+            controller: EndpointInfo {
+                id: self.endpoint_id,
+                label: self.label.clone(),
+                pid: Some(std::process::id()),
+                uid,
+                connected_at: self.started,
+                queue_depth: self.depth.load(Ordering::Relaxed),
+            },
+            endpoints: self
+                .endpoints
+                .iter()
+                .map(|ep| EndpointInfo {
+                    id: ep.id,
+                    label: ep.label.clone(),
+                    pid: ep.credentials.map(|(pid, _)| pid),
+                    uid: ep.credentials.map(|(_, uid)| uid),
+                    connected_at: ep.connected_at,
+                    queue_depth: ep.queue.pending.len(),
+                })
+                .collect(),
+            buffered,
+        };
+
+        let mut msg = Message::new(Selector::to_endpoint(source), info);
+        msg.selector.correlation = Some(Correlation::Reply(id));
+        self.send_to(msg);
+    }
+
+    /// How long to wait for a message before doing the periodic work.
+    fn recv_timeout(&self) -> Option<Duration> {
+        let monitored = self.monitor || self.endpoints.iter().any(|ep| ep.monitor);
+
+        if self.endpoints.iter().any(|ep| !ep.queue.pending.is_empty()) {
+            Some(FLUSH_INTERVAL)
+        } else if let (true, Some(expire)) = (
+            monitored,
+            self.message_buffer.iter().map(|(expire, _)| *expire).min(),
+        ) {
+            // Monitors are told when a buffered message expires
+            Some(expire.saturating_duration_since(Instant::now()))
+        } else if self.membership.is_some()
+            || self.endpoints.iter().any(|ep| ep.membership.is_some())
+        {
+            // Membership subscribers expect to learn about dead endpoints without other traffic
+            Some(DETECT_REACHABLE_INTERVAL)
+        } else {
+            None
+        }
+    }
+
+    fn flush_queues(&mut self) {
+        self.retain_endpoints(LeaveReason::Disconnect, |ep| {
+            !matches!(ep.queue.flush(&ep.remote), Err(Error::Disconnect))
+        });
+
+        // Release the senders whose endpoints drained their queue or left
+        let endpoints = &self.endpoints;
+        for hold in &mut self.holds {
+            hold.targets.retain(|target| {
+                endpoints
+                    .iter()
+                    .any(|ep| ep.id == *target && ep.queue.is_blocked())
+            });
+        }
+        let (released, holds) = mem::take(&mut self.holds)
+            .into_iter()
+            .partition::<Vec<_>, _>(|hold| hold.targets.is_empty());
+        self.holds = holds;
+
+        for hold in released {
+            log::debug!("release {}", hold.source);
+            self.io_hub.hold(hold.source, false);
+            for msg in hold.parked {
+                self.incoming.push_back(msg);
+            }
+        }
+    }
+
+    /// Stop reading from `source` while one of the endpoints it just sent to is blocked.
+    fn hold(&mut self, source: EndpointID, receivers: &[EndpointID]) {
+        let targets: Vec<_> = self
+            .endpoints
+            .iter()
+            .filter(|ep| receivers.contains(&ep.id) && ep.queue.is_blocked())
+            .map(|ep| ep.id)
+            .collect();
+        if targets.is_empty() {
+            return;
+        }
+
+        log::debug!("hold {} until {:?} read", source, targets);
+        self.io_hub.hold(source, true);
+        self.holds.push(Hold {
+            source,
+            targets,
+            parked: VecDeque::new(),
+        });
+    }
+
+    /// Keep a message of a held sender until it's released, returns other messages.
+    fn park(&mut self, msg: EncodedMessage) -> Option<EncodedMessage> {
+        let Some(hold) = self.holds.iter_mut().find(|hold| {
+            Some(hold.source) == msg.selector.source && !is_control(msg.selector.uuid)
+        }) else {
+            return Some(msg);
+        };
+
+        // Only platforms that can't stop reading a sender park more than a batch
+        if hold.parked.len() >= MESSAGE_BUFFER_CAPACITY {
+            log::warn!(
+                "too many messages of held {}, drop the message",
+                hold.source
+            );
+        } else {
+            hold.parked.push_back(msg);
+        }
+        None
+    }
+
+    /// Send `event` to every other endpoint subscribed to the label of the endpoint concerned.
+    fn publish(&mut self, event: MembershipEvent) {
+        let (id, label) = match &event {
+            MembershipEvent::EndpointJoined { id, label }
+            | MembershipEvent::EndpointLeft { id, label, .. } => (*id, label),
+        };
+
+        for subscriber in self.subscribers(id, |label_op| label_op.validate(label)) {
+            self.send_to(Message::new(
+                Selector::to_endpoint(subscriber),
+                event.clone(),
+            ));
+        }
+    }
+
+    /// Endpoint `id` changed its label, tell the subscribers for which it joined or left.
+    fn publish_relabel(&mut self, id: EndpointID, old_label: &Label) {
+        let label = match self.endpoints.iter().find(|ep| ep.id == id) {
+            Some(ep) => ep.label.clone(),
+            None => self.label.clone(),
+        };
+
+        let joined = self.subscribers(id, |label_op| {
+            !label_op.validate(old_label) && label_op.validate(&label)
+        });
+        let left = self.subscribers(id, |label_op| {
+            label_op.validate(old_label) && !label_op.validate(&label)
+        });
+
+        for subscriber in joined {
+            self.send_to(Message::new(
+                Selector::to_endpoint(subscriber),
+                MembershipEvent::EndpointJoined {
+                    id,
+                    label: label.clone(),
+                },
+            ));
+        }
+        for subscriber in left {
+            self.send_to(Message::new(
+                Selector::to_endpoint(subscriber),
+                MembershipEvent::EndpointLeft {
+                    id,
+                    label: label.clone(),
+                    reason: LeaveReason::Relabel,
+                },
+            ));
+        }
+    }
+
+    /// Endpoints other than `id` whose membership subscription satisfies `f`.
+    fn subscribers(&self, id: EndpointID, f: impl Fn(&LabelOp) -> bool) -> Vec<EndpointID> {
+        let mut subscribers = Vec::new();
+        if self.endpoint_id != id && matches!(&self.membership, Some(label_op) if f(label_op)) {
+            subscribers.push(self.endpoint_id);
+        }
+        subscribers.extend(
+            self.endpoints
+                .iter()
+                .filter(|ep| ep.id != id)
+                .filter(|ep| matches!(&ep.membership, Some(label_op) if f(label_op)))
+                .map(|ep| ep.id),
+        );
+        subscribers
+    }
+
+    /// Copy a message for the monitors, `None` if there is none or the message is for the bus controller.
+    fn tap(&self, encoded_msg: &EncodedMessage) -> Option<Tap> {
+        if !self.monitor && !self.endpoints.iter().any(|ep| ep.monitor) {
+            return None;
+        }
+
+        if is_control(encoded_msg.selector.uuid) {
+            return None;
+        }
+
+        let selector = &encoded_msg.selector;
+        Some(Tap {
+            source: selector.source,
+            label_op: selector.label_op.clone(),
+            mode: selector.mode,
+            target: selector.target,
+            priority: selector.priority,
+            sequence: selector.sequence,
+            uuid: selector.uuid,
+            size: encoded_msg.payload_data.len(),
+            payload: encoded_msg.payload_data.to_vec(),
+            spilled: selector.spilled,
+            routing: Routing::NoRoute,
+        })
+    }
+
+    /// Send `tap` to every monitor, it is never routed by label so it doesn't count as a receiver.
+    fn publish_tap(&mut self, tap: Option<Tap>, routing: Routing) {
+        let Some(mut tap) = tap else {
+            return;
+        };
+        tap.routing = routing;
+
+        // Encoded once, every monitor is sent the same packet
+        let mut encoded_msg = Message::new(Selector::multicast(LabelOp::True), tap).into_encoded();
+        for ep in self.endpoints.iter_mut().filter(|ep| ep.monitor) {
+            // A dead endpoint is removed on the next routing or reachability check
+            let _ = ep.queue.send(&ep.remote, &mut encoded_msg);
+        }
+        if self.monitor {
+            let _ = self.send_local(encoded_msg);
+        }
+    }
+
+    /// Deliver a message addressed with [`Selector::to_endpoint`].
+    fn send_to<T: MessageBox>(&mut self, msg: Message<T>) {
+        let Some(target) = msg.selector.target else {
+            return;
+        };
+        let mut encoded_msg = msg.into_encoded();
+
+        if target == self.endpoint_id {
+            let _ = self.send_local(encoded_msg);
+        } else if let Some(ep) = self.endpoints.iter_mut().find(|ep| ep.id == target) {
+            // A dead endpoint is removed on the next routing or reachability check
+            let _ = ep.queue.send(&ep.remote, &mut encoded_msg);
+        }
+    }
+
+    /// Remove endpoints for which `f` returns false, and publish that they left.
+    fn retain_endpoints(&mut self, reason: LeaveReason, mut f: impl FnMut(&mut Endpoint) -> bool) {
+        let mut left = Vec::new();
+        self.endpoints.retain_mut(|ep| {
+            let keep = f(ep);
+            if !keep {
+                left.push((ep.id, ep.label.clone()));
+            }
+            keep
+        });
+
+        for (id, label) in left {
+            let (lost, unacked): (VecDeque<_>, _) = mem::take(&mut self.unacked)
+                .into_iter()
+                .partition(|(receiver, _)| *receiver == id);
+            self.unacked = unacked;
+            self.redeliveries
+                .extend(lost.into_iter().map(|(_, msg)| msg));
+            COUNTERS.forget(id);
+
+            self.publish(MembershipEvent::EndpointLeft { id, label, reason });
+        }
+    }
+
     fn detect_reachable(&mut self, now: Instant) {
-        if now - self.last_detect_reachable > Duration::from_secs(30) {
-            self.endpoints.retain(|ep| !ep.remote.is_dead());
+        if now - self.last_detect_reachable >= DETECT_REACHABLE_INTERVAL {
+            self.retain_endpoints(LeaveReason::Unreachable, |ep| !ep.remote.is_dead());
 
             self.last_detect_reachable = now;
         }
     }
 
     fn maintain(&mut self, now: Instant) {
-        self.message_buffer.retain(|(expire, _)| *expire > now);
+        // A message expires at the end of its ttl or at its deadline, whichever comes first
+        let is_expired =
+            |(expire, msg): &(Instant, EncodedMessage)| *expire <= now || msg.selector.is_expired();
+        if !self.message_buffer.iter().any(is_expired) {
+            return;
+        }
+
+        let (expired, live) = mem::take(&mut self.message_buffer)
+            .into_iter()
+            .partition::<Vec<_>, _>(is_expired);
+        self.message_buffer = live;
+
+        for (_, msg) in expired {
+            metrics::increment(&COUNTERS.expired);
+            self.unspool(Spool::key(&msg));
+            self.publish_tap(self.tap(&msg), Routing::Expired);
+        }
     }
 }
 
-#[derive(PartialEq)]
 struct Endpoint {
     id: EndpointID,
     label: Label,
+    /// Pid and uid of the process, where the platform reports them.
+    credentials: Option<(u32, u32)>,
     remote: Remote,
+    membership: Option<LabelOp>,
+    monitor: bool,
+    queue: Queue,
+    connected_at: SystemTime,
+}
+
+/// A sender not read from until the queues of `targets` drain, see `Overflow::Block`.
+struct Hold {
+    source: EndpointID,
+    targets: Vec<EndpointID>,
+    /// Messages of the sender read before it was held.
+    parked: VecDeque<EncodedMessage>,
+}
+
+/// Messages held for an endpoint that doesn't read fast enough, sent by priority.
+struct Queue {
+    pending: PriorityQueue,
+    capacity: usize,
+    overflow: Overflow,
+}
+
+impl Queue {
+    /// Nothing is queued until the endpoint is configured, a message that can't be sent right away
+    /// holds up its sender.
+    fn new() -> Self {
+        Self {
+            pending: Default::default(),
+            capacity: 0,
+            overflow: Overflow::Block,
+        }
+    }
+
+    /// Send after the pending messages, `Err(Error::Full)` means the message was not routed to the endpoint.
+    ///
+    /// A copy is queued when the platform buffer is full, `encoded_msg` may still be sent to other endpoints.
+    /// Never blocks, with `Overflow::Block` the queue takes one message over its capacity per sender,
+    /// see `is_blocked`.
+    fn send(&mut self, remote: &Remote, encoded_msg: &mut EncodedMessage) -> Result<(), Error> {
+        self.flush(remote)?;
+
+        if self.pending.is_empty() {
+            match encoded_msg.send_timeout(remote, Duration::ZERO) {
+                Err(Error::Timeout) => {}
+                r => return r,
+            }
+        }
+
+        if self.pending.len() >= self.capacity {
+            match self.overflow {
+                // The sender is held until the queue drains
+                Overflow::Block => {}
+                Overflow::DropOldest if self.capacity > 0 => {
+                    log::debug!("queue full, drop the oldest message");
+                    self.pending.pop_oldest();
+                }
+                _ => {
+                    log::debug!("queue full, drop the message");
+                    return Err(Error::Full);
+                }
+            }
+        }
+
+        self.pending.push_back(duplicate(encoded_msg)?);
+        Ok(())
+    }
+
+    /// Over capacity, senders to the endpoint are held.
+    fn is_blocked(&self) -> bool {
+        self.overflow == Overflow::Block && self.pending.len() > self.capacity
+    }
+
+    fn flush(&mut self, remote: &Remote) -> Result<(), Error> {
+        while let Some(front) = self.pending.front_mut() {
+            match front.send_timeout(remote, Duration::ZERO) {
+                Ok(_) => {
+                    self.pending.pop_front();
+                }
+                Err(Error::Timeout) => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether a message of type `uuid` is for the bus controller, not routed.
+fn is_control(uuid: Bytes) -> bool {
+    match uuid {
+        <ConnectMessage as TypeUuid>::UUID
+        | <ConfigureMessage as TypeUuid>::UUID
+        | <AckMessage as TypeUuid>::UUID
+        | <QueryMessage as TypeUuid>::UUID => true,
+        #[cfg(windows)]
+        <crate::message::FetchProcessHandleMessage as TypeUuid>::UUID => true,
+        _ => false,
+    }
+}
+
+/// What became of a message handled once, `expired` if its deadline had passed.
+fn routing(expired: bool, remain: Option<&EncodedMessage>, receivers: Vec<EndpointID>) -> Routing {
+    match remain {
+        _ if !receivers.is_empty() => Routing::Routed { receivers },
+        _ if expired => Routing::Expired,
+        Some(remain) if !remain.selector.ttl.is_zero() => Routing::Buffered {
+            ttl: remain.selector.ttl,
+        },
+        _ => Routing::NoRoute,
+    }
+}
+
+/// A copy of `encoded_msg` to keep until it's acked, if it's reliable.
+fn reliable_copy(encoded_msg: &EncodedMessage) -> Option<EncodedMessage> {
+    if !encoded_msg.selector.reliable || encoded_msg.selector.sequence.is_none() {
+        return None;
+    }
+
+    duplicate(encoded_msg)
+        .map_err(|err| log::error!("reliable copy: {:?}", err))
+        .ok()
+}
+
+/// Encode a copy of a received message, objects are duplicated.
+fn duplicate(encoded_msg: &EncodedMessage) -> Result<EncodedMessage, Error> {
+    let mut msg = Message::new(
+        encoded_msg.selector.clone(),
+        RawPayload {
+            uuid: encoded_msg.selector.uuid,
+            codec: encoded_msg.selector.codec,
+            data: encoded_msg.payload_data,
+        },
+    );
+    msg.objects = encoded_msg
+        .objects
+        .iter()
+        .map(Object::clone)
+        .collect::<Result<_, _>>()?;
+    msg.memory_regions = encoded_msg
+        .memory_regions
+        .iter()
+        .map(MemoryRegion::clone)
+        .collect::<Result<_, _>>()?;
+
+    Ok(msg.into_encoded())
+}
+
+/// The payload of a message forwarded as is.
+struct RawPayload {
+    uuid: Bytes,
+    codec: u8,
+    data: &'static [u8],
+}
+
+impl MessageBox for RawPayload {
+    fn decode(_uuid: Bytes, _data: &[u8]) -> Result<Self, Error> {
+        Err(Error::TypeUuidNotFound)
+    }
+
+    fn encode(&self) -> Result<Vec<u8>, Error> {
+        Ok(self.data.to_vec())
+    }
+
+    fn uuid(&self) -> Bytes {
+        self.uuid
+    }
+
+    fn codec(&self) -> u8 {
+        self.codec
+    }
 }
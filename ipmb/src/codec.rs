@@ -0,0 +1,129 @@
+//! Payload codecs, a message type picks one with `#[ipmb(codec = ...)]` on a `#[derive(MessageBox)]` enum.
+//!
+//! The codec id is carried with every message, so a receiver decodes payloads encoded by peers
+//! with another built-in codec.
+
+use crate::Error;
+use bincode::error::DecodeError;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+pub trait Codec {
+    /// Identifies the codec on the wire, ids below 16 are reserved for the codecs of ipmb.
+    const ID: u8;
+
+    fn encode<T: Serialize>(t: &T) -> Result<Vec<u8>, Error>;
+
+    fn decode<T: DeserializeOwned>(data: &[u8]) -> Result<T, Error>;
+}
+
+/// The default codec.
+pub struct Bincode;
+
+impl Codec for Bincode {
+    const ID: u8 = 0;
+
+    fn encode<T: Serialize>(t: &T) -> Result<Vec<u8>, Error> {
+        crate::encode(t)
+    }
+
+    fn decode<T: DeserializeOwned>(data: &[u8]) -> Result<T, Error> {
+        crate::decode(data)
+    }
+}
+
+#[cfg(feature = "json")]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl Codec for Json {
+    const ID: u8 = 1;
+
+    fn encode<T: Serialize>(t: &T) -> Result<Vec<u8>, Error> {
+        serde_json::to_vec(t).map_err(encode_error)
+    }
+
+    fn decode<T: DeserializeOwned>(data: &[u8]) -> Result<T, Error> {
+        serde_json::from_slice(data).map_err(decode_error)
+    }
+}
+
+/// MessagePack with struct fields encoded as maps.
+#[cfg(feature = "msgpack")]
+pub struct MessagePack;
+
+#[cfg(feature = "msgpack")]
+impl Codec for MessagePack {
+    const ID: u8 = 2;
+
+    fn encode<T: Serialize>(t: &T) -> Result<Vec<u8>, Error> {
+        rmp_serde::to_vec_named(t).map_err(encode_error)
+    }
+
+    fn decode<T: DeserializeOwned>(data: &[u8]) -> Result<T, Error> {
+        rmp_serde::from_slice(data).map_err(decode_error)
+    }
+}
+
+#[cfg(feature = "cbor")]
+pub struct Cbor;
+
+#[cfg(feature = "cbor")]
+impl Codec for Cbor {
+    const ID: u8 = 3;
+
+    fn encode<T: Serialize>(t: &T) -> Result<Vec<u8>, Error> {
+        let mut data = Vec::new();
+        ciborium::into_writer(t, &mut data).map_err(encode_error)?;
+        Ok(data)
+    }
+
+    fn decode<T: DeserializeOwned>(data: &[u8]) -> Result<T, Error> {
+        ciborium::from_reader(data).map_err(decode_error)
+    }
+}
+
+/// Decode a payload encoded with codec `id`, which is either `C` or one of the built-in codecs.
+pub fn decode<C: Codec, T: DeserializeOwned>(id: u8, data: &[u8]) -> Result<T, Error> {
+    match id {
+        _ if id == C::ID => C::decode(data),
+        <Bincode as Codec>::ID => Bincode::decode(data),
+        #[cfg(feature = "json")]
+        <Json as Codec>::ID => Json::decode(data),
+        #[cfg(feature = "msgpack")]
+        <MessagePack as Codec>::ID => MessagePack::decode(data),
+        #[cfg(feature = "cbor")]
+        <Cbor as Codec>::ID => Cbor::decode(data),
+        _ => Err(not_found(id)),
+    }
+}
+
+/// Decode a payload encoded with the built-in codec `id`, borrowing from `data` where `T` does.
+pub fn decode_borrowed<'de, T: Deserialize<'de>>(id: u8, data: &'de [u8]) -> Result<T, Error> {
+    match id {
+        <Bincode as Codec>::ID => crate::decode(data),
+        #[cfg(feature = "json")]
+        <Json as Codec>::ID => serde_json::from_slice(data).map_err(decode_error),
+        #[cfg(feature = "msgpack")]
+        <MessagePack as Codec>::ID => rmp_serde::from_slice(data).map_err(decode_error),
+        // ciborium only decodes owned values
+        #[cfg(feature = "cbor")]
+        <Cbor as Codec>::ID => Err(Error::Decode(DecodeError::Other(
+            "cbor payloads can't be borrowed",
+        ))),
+        _ => Err(not_found(id)),
+    }
+}
+
+pub(crate) fn not_found(id: u8) -> Error {
+    Error::Decode(DecodeError::OtherString(format!("codec not found: {id}")))
+}
+
+#[cfg(any(feature = "json", feature = "msgpack", feature = "cbor"))]
+fn encode_error(err: impl std::fmt::Display) -> Error {
+    Error::Encode(bincode::error::EncodeError::OtherString(err.to_string()))
+}
+
+#[cfg(any(feature = "json", feature = "msgpack", feature = "cbor"))]
+fn decode_error(err: impl std::fmt::Display) -> Error {
+    Error::Decode(DecodeError::OtherString(err.to_string()))
+}
@@ -1,4 +1,4 @@
-use crate::{platform::Remote, Version};
+use crate::{platform::Remote, EndpointID, Version};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -30,6 +30,8 @@ pub enum Error {
     MemoryRegionMapping,
     #[error("permission denied")]
     PermissionDenied,
+    #[error("queue full")]
+    Full,
     #[error("unknown error")]
     Unknown,
 }
@@ -50,12 +52,19 @@ pub enum JoinError {
 pub enum SendError {
     #[error("timeout")]
     Timeout,
+    /// The message could not be sent without blocking, see [`try_send`](crate::EndpointSender::try_send).
+    #[error("full")]
+    Full,
     #[error("version mismatch: {0}")]
     VersionMismatch(Version),
     #[error("token mismatch")]
     TokenMismatch,
     #[error("permission denied")]
     PermissionDenied,
+    /// The payload is larger than [`spill_threshold`](crate::Options::spill_threshold) and
+    /// could not be moved into a memory region.
+    #[error("spill failed")]
+    Spill,
 }
 
 impl From<JoinError> for SendError {
@@ -81,6 +90,16 @@ pub enum RecvError {
     TokenMismatch,
     #[error("permission denied")]
     PermissionDenied,
+    /// Messages `from..to` of `sender` were lost, see [`Options::ordered`](crate::Options::ordered).
+    #[error("messages {from}..{to} of {sender} were lost")]
+    Gap {
+        sender: EndpointID,
+        from: u64,
+        to: u64,
+    },
+    /// The receiving half of the endpoint was dropped.
+    #[error("reader closed")]
+    ReaderClosed,
 }
 
 impl From<JoinError> for RecvError {
@@ -93,3 +112,52 @@ impl From<JoinError> for RecvError {
         }
     }
 }
+
+/// A textual [`LabelOp`](crate::LabelOp) could not be parsed, positions are byte offsets.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ParseError {
+    #[error("unexpected character '{1}' at {0}")]
+    UnexpectedChar(usize, char),
+    #[error("unexpected end at {0}")]
+    UnexpectedEnd(usize),
+    #[error("unterminated string at {0}")]
+    UnterminatedString(usize),
+    #[error("invalid escape at {0}")]
+    InvalidEscape(usize),
+}
+
+impl ParseError {
+    pub fn position(&self) -> usize {
+        match self {
+            Self::UnexpectedChar(position, _)
+            | Self::UnexpectedEnd(position)
+            | Self::UnterminatedString(position)
+            | Self::InvalidEscape(position) => *position,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum RequestError {
+    #[error("send error: {0}")]
+    Send(#[from] SendError),
+    #[error("recv error: {0}")]
+    Recv(#[from] RecvError),
+    #[error("timeout")]
+    Timeout,
+    /// The endpoint rejoined the bus while waiting, the reply can no longer be routed to it.
+    #[error("disconnected")]
+    Disconnect,
+    /// The receiving half of the endpoint was dropped.
+    #[error("reader closed")]
+    ReaderClosed,
+    /// The reply is not of the expected type.
+    #[error("type uuid not found")]
+    TypeUuidNotFound,
+    /// The requester stopped waiting for the reply, see [`send_reply`](crate::EndpointSender::send_reply).
+    #[error("late reply")]
+    LateReply,
+    /// The requester left the bus, see [`send_reply`](crate::EndpointSender::send_reply).
+    #[error("caller gone")]
+    CallerGone,
+}
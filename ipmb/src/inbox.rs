@@ -0,0 +1,288 @@
+use crate::{
+    metrics,
+    platform::EncodedMessage,
+    priority::PriorityQueue,
+    sequence::{Expected, Order},
+    Correlation, RecvError, Sequence,
+};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    sync::{Condvar, Mutex, MutexGuard},
+    task::Waker,
+    time::{Duration, Instant},
+};
+use type_uuid::Bytes;
+
+/// The most messages read at once, so they are taken by priority.
+const PUMP_BATCH: usize = 64;
+
+/// Messages pulled from the connection but not yet consumed.
+///
+/// Only one thread reads the connection at a time, others wait for it to dispatch what it read.
+pub(crate) struct Inbox {
+    state: Mutex<State>,
+    cond: Condvar,
+}
+
+#[derive(Default)]
+pub(crate) struct State {
+    pumping: bool,
+    /// Messages for the receiver returned by `join`.
+    backlog: PriorityQueue,
+    /// The receiver returned by `join` was dropped, messages not split off are discarded.
+    backlog_closed: bool,
+    /// Messages of the types split off with `EndpointReceiver::split`, by type uuid.
+    splits: HashMap<Bytes, Split>,
+    /// Receivers alive, including split ones.
+    receivers: usize,
+    pub replies: HashMap<u64, Reply>,
+    /// Messages discarded because their deadline passed.
+    pub expired: u64,
+    /// Check the order of messages, see `Options::ordered`.
+    ordered: bool,
+    expected: Expected,
+    /// The gaps found ahead of messages not yet taken.
+    gaps: HashMap<Sequence, RecvError>,
+    /// Messages taken after their gap, returned by the next `take` of the same receiver.
+    held: HashMap<Option<Bytes>, EncodedMessage>,
+    /// Async receivers waiting for the dispatch of another thread.
+    wakers: Vec<Waker>,
+}
+
+#[derive(Default)]
+struct Split {
+    receivers: usize,
+    messages: PriorityQueue,
+}
+
+pub(crate) enum Reply {
+    Waiting,
+    Ready(Box<EncodedMessage>),
+    Disconnect,
+}
+
+impl State {
+    fn dispatch(&mut self, msg: EncodedMessage) {
+        match msg.selector.correlation {
+            Some(Correlation::Reply(id)) => match self.replies.get_mut(&id) {
+                Some(reply @ Reply::Waiting) => *reply = Reply::Ready(Box::new(msg)),
+                _ => log::debug!("drop late reply: {}", id),
+            },
+            _ => {
+                let messages = match self.splits.get_mut(&msg.selector.uuid) {
+                    Some(split) => &mut split.messages,
+                    None if !self.backlog_closed => &mut self.backlog,
+                    None => return,
+                };
+
+                // A redelivered message may follow later ones, it was lost by another endpoint
+                if let (true, Some(sequence), 0) = (
+                    self.ordered,
+                    msg.selector.sequence,
+                    msg.selector.redelivered,
+                ) {
+                    match self.expected.check(sequence) {
+                        Order::Next => {}
+                        Order::Gap { from, to } => {
+                            let gap = RecvError::Gap {
+                                sender: sequence.sender,
+                                from,
+                                to,
+                            };
+                            self.gaps.insert(sequence, gap);
+                        }
+                        Order::Late => {
+                            log::debug!("drop late message: {:?}", sequence);
+                            return;
+                        }
+                    }
+                }
+
+                messages.push_back(msg);
+            }
+        }
+    }
+
+    /// Take the next message for the receiver of `split`, `None` is the receiver returned by `join`.
+    ///
+    /// Messages whose deadline has passed are discarded. A message preceded by a gap is held
+    /// back by one take, which returns `RecvError::Gap` instead.
+    pub fn take(&mut self, split: Option<Bytes>) -> Option<Result<EncodedMessage, RecvError>> {
+        if let Some(msg) = self.held.remove(&split) {
+            return Some(Ok(msg));
+        }
+
+        loop {
+            let msg = match split {
+                Some(uuid) => self.splits.get_mut(&uuid)?.messages.pop_front(),
+                None => self.backlog.pop_front(),
+            }?;
+
+            let gap = msg
+                .selector
+                .sequence
+                .and_then(|sequence| self.gaps.remove(&sequence));
+
+            if msg.selector.is_expired() {
+                self.expired += 1;
+                metrics::increment(&metrics::COUNTERS.expired);
+                log::debug!("drop expired message");
+                match gap {
+                    Some(gap) => break Some(Err(gap)),
+                    None => continue,
+                }
+            }
+
+            match gap {
+                Some(gap) => {
+                    self.held.insert(split, msg);
+                    break Some(Err(gap));
+                }
+                None => break Some(Ok(msg)),
+            }
+        }
+    }
+}
+
+impl Inbox {
+    pub fn new(ordered: bool) -> Self {
+        Self {
+            state: Mutex::new(State {
+                receivers: 1,
+                ordered,
+                ..Default::default()
+            }),
+            cond: Condvar::new(),
+        }
+    }
+
+    /// Route messages of type `uuid` to a new receiver, including those already received.
+    pub fn split(&self, uuid: Bytes) {
+        let mut state = self.lock();
+        let state = &mut *state;
+        state.receivers += 1;
+
+        let split = state.splits.entry(uuid).or_default();
+        if split.receivers == 0 {
+            split.messages = state.backlog.extract(|msg| msg.selector.uuid == uuid);
+        }
+        split.receivers += 1;
+    }
+
+    /// Unregister a dropped receiver, returns `true` if it was the last one.
+    ///
+    /// Messages left to the last receiver of a split type go back to the receiver returned by `join`.
+    pub fn release(&self, split: Option<Bytes>) -> bool {
+        let mut state = self.lock();
+        let state = &mut *state;
+
+        match split {
+            Some(uuid) => {
+                if let Entry::Occupied(mut entry) = state.splits.entry(uuid) {
+                    entry.get_mut().receivers -= 1;
+                    if entry.get().receivers == 0 {
+                        let split = entry.remove();
+                        let held = state.held.remove(&Some(uuid));
+                        if !state.backlog_closed {
+                            held.into_iter()
+                                .for_each(|msg| state.backlog.push_back(msg));
+                            state.backlog.append(split.messages);
+                        }
+                    }
+                }
+            }
+            None => {
+                state.backlog_closed = true;
+                state.backlog.clear();
+                state.held.remove(&None);
+            }
+        }
+
+        state.receivers -= 1;
+        state.receivers == 0
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    /// Wait until `take` yields a value, returns `Ok(None)` on timeout.
+    ///
+    /// If no other thread is reading the connection, `pump` is called to read one message and then
+    /// those that have already arrived, `Ok(None)` from `pump` means nothing arrived before the timeout.
+    pub fn wait<T, E>(
+        &self,
+        timeout: Option<Duration>,
+        mut take: impl FnMut(&mut State) -> Option<T>,
+        mut pump: impl FnMut(Option<Duration>) -> Result<Option<EncodedMessage>, E>,
+    ) -> Result<Option<T>, E> {
+        let end = timeout.map(|timeout| Instant::now() + timeout);
+        let mut pumped = false;
+        let mut state = self.lock();
+
+        loop {
+            if let Some(v) = take(&mut state) {
+                break Ok(Some(v));
+            }
+
+            let remain = end.map(|end| end.saturating_duration_since(Instant::now()));
+            if pumped && remain.map(|remain| remain.is_zero()).unwrap_or(false) {
+                break Ok(None);
+            }
+
+            if state.pumping {
+                state = match remain {
+                    Some(remain) => self.cond.wait_timeout(state, remain).unwrap().0,
+                    None => self.cond.wait(state).unwrap(),
+                };
+                pumped = true;
+                continue;
+            }
+
+            state.pumping = true;
+            drop(state);
+
+            let r = pump(remain).map(|msg| {
+                let mut msgs: Vec<_> = msg.into_iter().collect();
+                // Errors show up again on the next read
+                while !msgs.is_empty() && msgs.len() < PUMP_BATCH {
+                    match pump(Some(Duration::ZERO)) {
+                        Ok(Some(msg)) => msgs.push(msg),
+                        _ => break,
+                    }
+                }
+                msgs
+            });
+
+            state = self.lock();
+            state.pumping = false;
+            self.cond.notify_all();
+            state.wakers.drain(..).for_each(Waker::wake);
+            pumped = true;
+
+            match r {
+                Ok(msgs) => msgs.into_iter().for_each(|msg| state.dispatch(msg)),
+                Err(err) => break Err(err),
+            }
+        }
+    }
+
+    #[cfg(feature = "async")]
+    pub fn register(&self, waker: &Waker) {
+        let mut state = self.lock();
+        if !state.wakers.iter().any(|w| w.will_wake(waker)) {
+            state.wakers.push(waker.clone());
+        }
+    }
+
+    /// Replies to pending requests can't arrive once the connection is reestablished.
+    pub fn disconnect(&self) {
+        let mut state = self.lock();
+        for reply in state.replies.values_mut() {
+            if let Reply::Waiting = reply {
+                *reply = Reply::Disconnect;
+            }
+        }
+        self.cond.notify_all();
+    }
+}
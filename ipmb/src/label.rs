@@ -1,7 +1,13 @@
+use crate::ParseError;
 use serde::{Deserialize, Serialize};
 use smallvec::SmallVec;
 use smol_str::SmolStr;
-use std::{iter, ops::Not};
+use std::{
+    fmt::{self, Display, Formatter},
+    iter,
+    ops::Not,
+    str::FromStr,
+};
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Label(SmallVec<[SmolStr; 8]>);
@@ -65,8 +71,10 @@ macro_rules! label {
 /// Route matching operations, e.g.
 /// ```rust
 /// ipmb::LabelOp::from("foo").and("bar").or(!ipmb::LabelOp::from("baz"));
+/// ipmb::LabelOp::prefix("renderer.").or(ipmb::LabelOp::glob("codec.*.hw"));
+/// ipmb::LabelOp::parse("renderer & (gpu | !software)").unwrap();
 /// ```
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum LabelOp {
     True,
     False,
@@ -74,6 +82,10 @@ pub enum LabelOp {
     Not(Box<Self>),
     And(Box<Self>, Box<Self>),
     Or(Box<Self>, Box<Self>),
+    /// Any label element starts with the prefix.
+    Prefix(SmolStr),
+    /// Any label element matches the pattern, `*` matches any characters and `?` a single one.
+    Glob(SmolStr),
 }
 
 impl LabelOp {
@@ -85,11 +97,35 @@ impl LabelOp {
         Self::Or(Box::new(self), Box::new(v.into()))
     }
 
+    pub fn prefix(v: impl Into<SmolStr>) -> Self {
+        Self::Prefix(v.into())
+    }
+
+    pub fn glob(v: impl Into<SmolStr>) -> Self {
+        Self::Glob(v.into())
+    }
+
+    /// Parse the textual form, which is also what `Display` prints, e.g. `renderer & (gpu | !software)`.
+    ///
+    /// Elements are bare words or double-quoted strings, `prefix("renderer.")` and `glob("codec.*.hw")`
+    /// match by prefix and pattern, `true` and `false` are constants. `!` binds tighter than `&`,
+    /// which binds tighter than `|`.
+    pub fn parse(s: &str) -> Result<Self, ParseError> {
+        let mut parser = Parser { s, pos: 0 };
+        let op = parser.or()?;
+        match parser.peek() {
+            Some(c) => Err(ParseError::UnexpectedChar(parser.pos, c)),
+            None => Ok(op),
+        }
+    }
+
     pub fn validate(&self, label: &Label) -> bool {
         match self {
             Self::True => true,
             Self::False => false,
             Self::Leaf(v) => label.all(iter::once(v.as_str())),
+            Self::Prefix(v) => label.iter().any(|s| s.starts_with(v.as_str())),
+            Self::Glob(v) => label.iter().any(|s| glob_match(v, s)),
             Self::And(left, right) => left.validate(label) && right.validate(label),
             Self::Or(left, right) => left.validate(label) || right.validate(label),
             Self::Not(left) => !left.validate(label),
@@ -97,6 +133,247 @@ impl LabelOp {
     }
 }
 
+fn glob_match(pattern: &str, s: &str) -> bool {
+    let pattern: SmallVec<[char; 32]> = pattern.chars().collect();
+    let s: SmallVec<[char; 32]> = s.chars().collect();
+
+    let (mut p, mut i) = (0, 0);
+    // Position after the last `*` and the input position it was tried at
+    let mut star = None;
+
+    while i < s.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, i));
+                p += 1;
+            }
+            Some('?') => {
+                p += 1;
+                i += 1;
+            }
+            Some(c) if *c == s[i] => {
+                p += 1;
+                i += 1;
+            }
+            _ => match star {
+                Some((star_p, star_i)) => {
+                    // Let the last `*` match one more character
+                    p = star_p;
+                    i = star_i + 1;
+                    star = Some((star_p, star_i + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+impl LabelOp {
+    fn precedence(&self) -> u8 {
+        match self {
+            Self::Or(..) => 1,
+            Self::And(..) => 2,
+            _ => 3,
+        }
+    }
+
+    fn fmt_operand(&self, f: &mut Formatter<'_>, precedence: u8) -> fmt::Result {
+        if self.precedence() < precedence {
+            write!(f, "({self})")
+        } else {
+            write!(f, "{self}")
+        }
+    }
+}
+
+impl Display for LabelOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::True => f.write_str("true"),
+            Self::False => f.write_str("false"),
+            Self::Leaf(v) => {
+                if v.chars().all(is_word_char) && !v.is_empty() && !KEYWORDS.contains(&v.as_str()) {
+                    f.write_str(v)
+                } else {
+                    fmt_string(f, v)
+                }
+            }
+            Self::Prefix(v) => {
+                f.write_str("prefix(")?;
+                fmt_string(f, v)?;
+                f.write_str(")")
+            }
+            Self::Glob(v) => {
+                f.write_str("glob(")?;
+                fmt_string(f, v)?;
+                f.write_str(")")
+            }
+            Self::Not(v) => {
+                f.write_str("!")?;
+                v.fmt_operand(f, 3)
+            }
+            // Both operators are parsed left-associative
+            Self::And(left, right) => {
+                left.fmt_operand(f, 2)?;
+                f.write_str(" & ")?;
+                right.fmt_operand(f, 3)
+            }
+            Self::Or(left, right) => {
+                left.fmt_operand(f, 1)?;
+                f.write_str(" | ")?;
+                right.fmt_operand(f, 2)
+            }
+        }
+    }
+}
+
+impl FromStr for LabelOp {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+const KEYWORDS: [&str; 4] = ["true", "false", "prefix", "glob"];
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | ':' | '/')
+}
+
+fn fmt_string(f: &mut Formatter<'_>, s: &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for c in s.chars() {
+        if matches!(c, '"' | '\\') {
+            f.write_str("\\")?;
+        }
+        write!(f, "{c}")?;
+    }
+    f.write_str("\"")
+}
+
+struct Parser<'a> {
+    s: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    /// The next character after whitespace.
+    fn peek(&mut self) -> Option<char> {
+        let rest = &self.s[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+        self.s[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self, c: char) {
+        self.pos += c.len_utf8();
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), ParseError> {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.bump(c);
+                Ok(())
+            }
+            Some(c) => Err(ParseError::UnexpectedChar(self.pos, c)),
+            None => Err(ParseError::UnexpectedEnd(self.pos)),
+        }
+    }
+
+    fn or(&mut self) -> Result<LabelOp, ParseError> {
+        let mut op = self.and()?;
+        while self.peek() == Some('|') {
+            self.bump('|');
+            op = op.or(self.and()?);
+        }
+        Ok(op)
+    }
+
+    fn and(&mut self) -> Result<LabelOp, ParseError> {
+        let mut op = self.unary()?;
+        while self.peek() == Some('&') {
+            self.bump('&');
+            op = op.and(self.unary()?);
+        }
+        Ok(op)
+    }
+
+    fn unary(&mut self) -> Result<LabelOp, ParseError> {
+        match self.peek() {
+            Some('!') => {
+                self.bump('!');
+                Ok(!self.unary()?)
+            }
+            Some('(') => {
+                self.bump('(');
+                let op = self.or()?;
+                self.expect(')')?;
+                Ok(op)
+            }
+            Some('"') => Ok(LabelOp::Leaf(self.string()?)),
+            Some(c) if is_word_char(c) => match self.word() {
+                "true" => Ok(LabelOp::True),
+                "false" => Ok(LabelOp::False),
+                word @ ("prefix" | "glob") if self.peek() == Some('(') => {
+                    self.bump('(');
+                    let v = self.atom()?;
+                    self.expect(')')?;
+                    Ok(if word == "prefix" {
+                        LabelOp::Prefix(v)
+                    } else {
+                        LabelOp::Glob(v)
+                    })
+                }
+                word => Ok(LabelOp::Leaf(word.into())),
+            },
+            Some(c) => Err(ParseError::UnexpectedChar(self.pos, c)),
+            None => Err(ParseError::UnexpectedEnd(self.pos)),
+        }
+    }
+
+    fn atom(&mut self) -> Result<SmolStr, ParseError> {
+        match self.peek() {
+            Some('"') => self.string(),
+            Some(c) if is_word_char(c) => Ok(self.word().into()),
+            Some(c) => Err(ParseError::UnexpectedChar(self.pos, c)),
+            None => Err(ParseError::UnexpectedEnd(self.pos)),
+        }
+    }
+
+    fn word(&mut self) -> &'a str {
+        let s = self.s;
+        let rest = &s[self.pos..];
+        let len = rest.find(|c| !is_word_char(c)).unwrap_or(rest.len());
+        self.pos += len;
+        &rest[..len]
+    }
+
+    fn string(&mut self) -> Result<SmolStr, ParseError> {
+        let start = self.pos;
+        self.bump('"');
+
+        let mut v = String::new();
+        let mut chars = self.s[self.pos..].char_indices();
+        loop {
+            match chars.next() {
+                Some((i, '"')) => {
+                    self.pos += i + 1;
+                    break Ok(v.into());
+                }
+                Some((i, '\\')) => match chars.next() {
+                    Some((_, c @ ('"' | '\\'))) => v.push(c),
+                    Some(_) => break Err(ParseError::InvalidEscape(self.pos + i)),
+                    None => break Err(ParseError::UnterminatedString(start)),
+                },
+                Some((_, c)) => v.push(c),
+                None => break Err(ParseError::UnterminatedString(start)),
+            }
+        }
+    }
+}
+
 impl Not for LabelOp {
     type Output = Self;
 
@@ -114,6 +391,7 @@ impl<T: Into<SmolStr>> From<T> for LabelOp {
 #[cfg(test)]
 mod test {
     use super::LabelOp;
+    use crate::ParseError;
 
     #[test]
     fn empty_label() {
@@ -168,4 +446,88 @@ mod test {
         assert!(op.validate(&label!("foo")));
         assert!(op.validate(&label!("bar")));
     }
+
+    #[test]
+    fn op_prefix() {
+        let op = LabelOp::prefix("renderer.");
+        assert!(op.validate(&label!("solar", "renderer.tab.42")));
+        assert!(!op.validate(&label!("renderer")));
+    }
+
+    #[test]
+    fn op_glob() {
+        let op = LabelOp::glob("codec.*.hw");
+        assert!(op.validate(&label!("codec.h264.hw")));
+        assert!(op.validate(&label!("codec..hw")));
+        assert!(!op.validate(&label!("codec.h264.sw")));
+        assert!(!op.validate(&label!("codec.h264.hw2")));
+
+        assert!(LabelOp::glob("tab.?").validate(&label!("tab.1")));
+        assert!(!LabelOp::glob("tab.?").validate(&label!("tab.12")));
+        assert!(LabelOp::glob("*").validate(&label!("")));
+        assert!(LabelOp::glob("a*b*c").validate(&label!("aXbYbZc")));
+    }
+
+    #[test]
+    fn parse() {
+        let op = LabelOp::parse("renderer & (gpu | !software)").unwrap();
+        assert_eq!(
+            op,
+            LabelOp::from("renderer").and(LabelOp::from("gpu").or(!LabelOp::from("software")))
+        );
+        assert!(op.validate(&label!("renderer", "gpu")));
+        assert!(!op.validate(&label!("renderer", "software")));
+
+        let op = LabelOp::parse(r#"prefix("renderer.") | glob("codec.*.hw") | "a b" | true"#);
+        assert_eq!(
+            op.unwrap(),
+            LabelOp::prefix("renderer.")
+                .or(LabelOp::glob("codec.*.hw"))
+                .or("a b")
+                .or(LabelOp::True)
+        );
+    }
+
+    #[test]
+    fn parse_error() {
+        assert_eq!(
+            LabelOp::parse("a & (b | c"),
+            Err(ParseError::UnexpectedEnd(10))
+        );
+        assert_eq!(
+            LabelOp::parse("a & | b"),
+            Err(ParseError::UnexpectedChar(4, '|'))
+        );
+        assert_eq!(
+            LabelOp::parse("a b"),
+            Err(ParseError::UnexpectedChar(2, 'b'))
+        );
+        assert_eq!(
+            LabelOp::parse(r#"a | "b"#),
+            Err(ParseError::UnterminatedString(4))
+        );
+        assert_eq!(LabelOp::parse(r#""\n""#), Err(ParseError::InvalidEscape(1)));
+    }
+
+    #[test]
+    fn display_round_trip() {
+        let ops = [
+            LabelOp::from("a").and("b").and("c"),
+            LabelOp::from("a").and(LabelOp::from("b").and("c")),
+            LabelOp::from("a").or("b").and("c"),
+            !LabelOp::from("a").or(!LabelOp::from("b")),
+            LabelOp::from("true").or("say \"hi\"\\").or(""),
+            LabelOp::prefix("renderer.").and(!LabelOp::glob("codec.*.hw")),
+            LabelOp::False.or(LabelOp::True),
+        ];
+
+        for op in ops {
+            assert_eq!(LabelOp::parse(&op.to_string()), Ok(op));
+        }
+
+        assert_eq!(
+            LabelOp::from("a").or("b").and("c").to_string(),
+            "(a | b) & c"
+        );
+    }
 }
@@ -1,37 +1,62 @@
 use bus_controller::BusController;
-pub use errors::{Error, JoinError, RecvError, SendError};
+pub use codec::Codec;
+pub use errors::{Error, JoinError, ParseError, RecvError, RequestError, SendError};
+use inbox::{Inbox, Reply};
 pub use ipmb_derive::MessageBox;
 pub use label::{Label, LabelOp};
 pub use memory_registry::MemoryRegistry;
-pub use message::{BytesMessage, Message, MessageBox};
+use message::{AckMessage, ConfigureMessage, QueryMessage};
+pub use message::{
+    Borrowed, BusInfo, BytesMessage, Delivery, EndpointInfo, LeaveReason, MembershipEvent, Message,
+    MessageBox, Routing, Tap,
+};
+pub use metrics::{Metrics, RouteMetrics};
 use once_cell::sync::Lazy;
-pub use options::Options;
+pub use options::{Options, Overflow};
 use platform::{look_up, register, EncodedMessage, IoHub, IoMultiplexing, Remote};
 pub use platform::{MemoryRegion, Object};
+pub use priority::Priority;
+pub use sequence::Sequence;
+use sequence::Sequencer;
 use serde::{Deserialize, Serialize};
 use std::{
     fmt::{Display, Formatter},
     marker::PhantomData,
+    mem,
     sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
         mpsc,
-        mpsc::{Receiver, RecvTimeoutError, Sender},
+        mpsc::{Receiver, RecvTimeoutError, SyncSender, TrySendError},
         Arc, Mutex, RwLock,
     },
     thread,
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime},
 };
-use type_uuid::Bytes;
-use util::EndpointID;
+pub use trace::{TraceContext, TraceGuard};
+use type_uuid::{Bytes, TypeUuid};
+pub use util::EndpointID;
 
+#[cfg(feature = "async")]
+mod async_io;
 mod bus_controller;
+pub mod codec;
 mod errors;
+mod inbox;
 mod label;
 mod memory_registry;
 mod message;
+mod metrics;
 mod options;
 pub mod platform;
+mod priority;
+mod sequence;
+mod spool;
+mod trace;
 mod util;
 
+#[cfg(all(feature = "async", not(target_os = "linux")))]
+compile_error!("feature `async` is only supported on Linux");
+
 /// Describe how a messages is routed.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Selector {
@@ -41,26 +66,78 @@ pub struct Selector {
     memory_region_count: u16,
     /// The time to live when a message cannot be routed to any endpoint.
     pub ttl: Duration,
+    /// Messages of higher priority are routed and received ahead of those waiting.
+    pub priority: Priority,
+    /// The message is dropped by the bus controller or the receiver once this time has passed.
+    pub deadline: Option<SystemTime>,
+    /// The bus controller keeps the message until the receiver acks it with [`EndpointReceiver::ack`],
+    /// and routes it again if the receiver leaves the bus before.
+    pub reliable: bool,
+    source: Option<EndpointID>,
+    target: Option<EndpointID>,
+    correlation: Option<Correlation>,
+    /// The [`Codec`] of the payload.
+    codec: u8,
+    /// The payload is in the last memory region, see [`Options::spill_threshold`].
+    spilled: bool,
+    sequence: Option<Sequence>,
+    /// See [`Message::redelivered`].
+    redelivered: u32,
+    /// Set when sending from a thread that entered a trace, see [`TraceContext`].
+    pub trace: Option<TraceContext>,
 }
 
 impl Selector {
     pub fn unicast(label_op: impl Into<LabelOp>) -> Self {
-        Self {
-            label_op: label_op.into(),
-            mode: SelectorMode::Unicast,
-            uuid: [0; 16],
-            memory_region_count: 0,
-            ttl: Duration::ZERO,
-        }
+        Self::new(label_op.into(), SelectorMode::Unicast)
     }
 
     pub fn multicast(label_op: impl Into<LabelOp>) -> Self {
+        Self::new(label_op.into(), SelectorMode::Multicast)
+    }
+
+    /// Route to the endpoint with `id` only, regardless of its label.
+    /// The message is dropped if the endpoint is not on the bus, it's never buffered for `ttl`.
+    /// A [`reliable`](Selector::reliable) one is not redelivered if the endpoint leaves without acking it.
+    pub fn to_endpoint(id: EndpointID) -> Self {
+        let mut selector = Self::new(LabelOp::True, SelectorMode::Unicast);
+        selector.target = Some(id);
+        selector
+    }
+
+    /// Set the deadline to `timeout` from now.
+    pub fn expire_after(&mut self, timeout: Duration) {
+        self.deadline = Some(SystemTime::now() + timeout);
+    }
+
+    /// Whether the deadline has passed.
+    pub fn is_expired(&self) -> bool {
+        matches!(self.deadline, Some(deadline) if deadline <= SystemTime::now())
+    }
+
+    /// The endpoint this selector is addressed to, see [`to_endpoint`](Selector::to_endpoint).
+    pub fn target(&self) -> Option<EndpointID> {
+        self.target
+    }
+
+    fn new(label_op: LabelOp, mode: SelectorMode) -> Self {
         Self {
-            label_op: label_op.into(),
-            mode: SelectorMode::Multicast,
+            label_op,
+            mode,
             uuid: [0; 16],
             memory_region_count: 0,
             ttl: Duration::ZERO,
+            priority: Priority::Normal,
+            deadline: None,
+            reliable: false,
+            source: None,
+            target: None,
+            correlation: None,
+            codec: 0,
+            spilled: false,
+            sequence: None,
+            redelivered: 0,
+            trace: None,
         }
     }
 }
@@ -73,6 +150,24 @@ pub enum SelectorMode {
     Multicast,
 }
 
+/// Pairs a request with its reply.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, Eq, PartialEq)]
+enum Correlation {
+    Request(u64),
+    Reply(u64),
+    /// The bus controller replies with a [`Delivery`].
+    Confirm(u64),
+    /// A reply whose [`Delivery`] is reported as for `Confirm`, see [`EndpointSender::send_reply`].
+    ConfirmReply {
+        reply: u64,
+        confirm: u64,
+    },
+}
+
+static CORRELATION_ID: AtomicU64 = AtomicU64::new(0);
+/// How often a sender in the bus controller's process looks for room in a full channel.
+const BUS_RETRY_INTERVAL: Duration = Duration::from_millis(1);
+
 pub fn decode<'de, T: Deserialize<'de>>(data: &'de [u8]) -> Result<T, Error> {
     let (d, _): (T, _) =
         bincode::serde::borrow_decode_from_slice(data, bincode::config::standard())
@@ -89,20 +184,28 @@ pub fn join<'de, T: MessageBox, R: MessageBox>(
     options: Options,
     timeout: Option<Duration>,
 ) -> Result<(EndpointSender<T>, EndpointReceiver<R>), JoinError> {
+    let ordered = options.ordered;
     let rule = Arc::new(RwLock::new(Rule::join(
         options,
         0,
         Arc::new(IoMultiplexing::new()),
         timeout,
     )?));
+    let inbox = Arc::new(Inbox::new(ordered));
 
     Ok((
         EndpointSender {
             rule: rule.clone(),
+            inbox: inbox.clone(),
+            sequencer: Arc::new(Mutex::new(Sequencer::new())),
             _marker: PhantomData,
         },
         EndpointReceiver {
             rule,
+            inbox,
+            split: None,
+            #[cfg(feature = "async")]
+            readiness: Default::default(),
             _maker: PhantomData,
         },
     ))
@@ -111,6 +214,8 @@ pub fn join<'de, T: MessageBox, R: MessageBox>(
 /// The sending half of endpoint, messages can be sent with [`send`](EndpointSender::send).
 pub struct EndpointSender<T> {
     rule: Arc<RwLock<Rule>>,
+    inbox: Arc<Inbox>,
+    sequencer: Arc<Mutex<Sequencer>>,
     _marker: PhantomData<T>,
 }
 
@@ -118,78 +223,300 @@ impl<T> Clone for EndpointSender<T> {
     fn clone(&self) -> Self {
         Self {
             rule: self.rule.clone(),
+            inbox: self.inbox.clone(),
+            sequencer: self.sequencer.clone(),
             _marker: PhantomData,
         }
     }
 }
 
 impl<T: MessageBox> EndpointSender<T> {
-    pub fn send(&self, mut msg: Message<T>) -> Result<(), SendError> {
-        msg.selector.memory_region_count = msg.memory_regions.len() as _;
-        let mut msg = msg.into_encoded();
+    pub fn send(&self, msg: Message<T>) -> Result<(), SendError> {
+        self.send_inner(msg, None)
+    }
+
+    /// Like [`send`](EndpointSender::send), but returns `SendError::Timeout` if the bus controller
+    /// doesn't take the message within `timeout`.
+    ///
+    /// Fails when the bus controller holds up this endpoint because it sent to an endpoint with
+    /// [`Overflow::Block`] that doesn't read, on Linux and in the bus controller's process. It
+    /// always blocks on Windows.
+    pub fn send_timeout(&self, msg: Message<T>, timeout: Duration) -> Result<(), SendError> {
+        self.send_inner(msg, Some(timeout))
+    }
+
+    /// Like [`send`](EndpointSender::send), but returns `SendError::Full` instead of blocking.
+    pub fn try_send(&self, msg: Message<T>) -> Result<(), SendError> {
+        match self.send_inner(msg, Some(Duration::ZERO)) {
+            Err(SendError::Timeout) => Err(SendError::Full),
+            r => r,
+        }
+    }
+
+    fn send_inner<P: MessageBox>(
+        &self,
+        msg: Message<P>,
+        timeout: Option<Duration>,
+    ) -> Result<(), SendError> {
+        let end = timeout.map(|timeout| Instant::now() + timeout);
+        // Held until the message is sent, so numbers are sent in order and none is used up by a failed send
+        let mut sequencer = self.sequencer.lock().unwrap();
+        let mut msg = self.encode(msg, &sequencer)?;
+        let sequence = msg.selector.sequence;
+        #[cfg(feature = "tracing")]
+        let _span = trace::send_span(&msg.selector).entered();
+
+        let r = loop {
+            let timeout = end.map(|end| end.saturating_duration_since(Instant::now()));
 
-        loop {
             let rule = self.rule.read().unwrap();
             match &*rule {
                 Rule::Client {
                     endpoint_id: _,
                     options: _,
+                    label: _,
                     remote,
                     io_hub: _,
                     reader_closed: _,
                     im: _,
                     epoch,
-                } => match msg.send(remote) {
-                    Err(Error::Disconnect) => {
-                        let epoch = *epoch;
-                        drop(rule);
+                } => {
+                    let r = match timeout {
+                        Some(timeout) => msg.send_timeout(remote, timeout),
+                        None => msg.send(remote),
+                    };
 
-                        let mut rule = self.rule.write().unwrap();
-                        match &mut *rule {
-                            Rule::Client {
-                                endpoint_id: _,
-                                options,
-                                remote: _,
-                                io_hub,
-                                reader_closed,
-                                im,
-                                epoch: epoch1,
-                            } => {
-                                if epoch == *epoch1 {
-                                    let reader_closed = *reader_closed;
-
-                                    // Close reader
-                                    drop(io_hub.take());
-
-                                    *rule = Rule::join(
-                                        options.clone(),
-                                        epoch.overflowing_add(1).0,
-                                        im.clone(),
-                                        None,
-                                    )?;
-
-                                    if reader_closed {
-                                        rule.reader_close();
-                                    }
-                                }
-                            }
-                            Rule::Server { .. } => {}
+                    match r {
+                        Err(Error::Disconnect) => {
+                            let epoch = *epoch;
+                            drop(rule);
+
+                            rejoin(&self.rule, &self.inbox, epoch, timeout)?;
                         }
+                        Err(Error::Timeout) => break Err(SendError::Timeout),
+                        Err(_) => unreachable!(),
+                        Ok(_) => break Ok(()),
                     }
-                    Err(_) => unreachable!(),
-                    Ok(_) => break Ok(()),
-                },
+                }
                 Rule::Server {
                     endpoint_id: _,
                     bus_sender,
                     receiver: _,
                     im,
+                    ..
                 } => {
-                    bus_sender.lock().unwrap().send(msg).unwrap();
-                    im.wake();
-                    break Ok(());
+                    let bus_sender = bus_sender.lock().unwrap().clone();
+                    let r = send_bus(&bus_sender, msg, end);
+                    if r.is_ok() {
+                        im.wake();
+                    }
+                    break r;
                 }
             }
+        };
+
+        if r.is_ok() {
+            sequencer.advance(sequence);
+        }
+        r
+    }
+
+    /// The id of this endpoint, it changes when the endpoint rejoins the bus.
+    pub fn endpoint_id(&self) -> EndpointID {
+        self.rule.read().unwrap().endpoint_id()
+    }
+
+    /// Replace the label of this endpoint without rejoining the bus, messages are routed by the new
+    /// label as soon as the bus controller handles the change.
+    pub fn set_label(&self, label: Label) {
+        self.rule
+            .read()
+            .unwrap()
+            .update_label(|current| *current = label);
+    }
+
+    /// Add an element to the label of this endpoint, see [`set_label`](EndpointSender::set_label).
+    pub fn insert_label<S: AsRef<str>>(&self, s: S) {
+        self.rule
+            .read()
+            .unwrap()
+            .update_label(|label| label.insert(s));
+    }
+
+    /// Remove an element from the label of this endpoint, see [`set_label`](EndpointSender::set_label).
+    pub fn remove_label(&self, s: &str) {
+        self.rule
+            .read()
+            .unwrap()
+            .update_label(|label| label.remove(s));
+    }
+
+    fn encode<P: MessageBox>(
+        &self,
+        mut msg: Message<P>,
+        sequencer: &Sequencer,
+    ) -> Result<EncodedMessage, SendError> {
+        let (source, spill_threshold) = {
+            let rule = self.rule.read().unwrap();
+            (rule.endpoint_id(), rule.options().spill_threshold)
+        };
+
+        sequencer.stamp(&mut msg.selector);
+        trace::inject(&mut msg.selector);
+        let mut msg = msg.spill(spill_threshold.min(platform::MAX_SPILL_THRESHOLD))?;
+        msg.selector.memory_region_count = msg.memory_regions.len() as _;
+        msg.selector.source = Some(source);
+        Ok(msg.into_encoded())
+    }
+
+    /// Send a request and wait for the reply built with [`Message::reply`].
+    ///
+    /// Replies arrive at the receiving half of this endpoint, they are picked up by whichever
+    /// thread is reading it, so the [`EndpointReceiver`] must be kept alive.
+    pub fn request<R: MessageBox>(
+        &self,
+        selector: Selector,
+        payload: T,
+        timeout: Option<Duration>,
+    ) -> Result<Message<R>, RequestError> {
+        let mut msg = Message::new(selector, payload);
+        // Nobody waits for the reply after the timeout, the replier learns it from the deadline
+        if let Some(timeout) = timeout {
+            let deadline = SystemTime::now() + timeout;
+            msg.selector.deadline =
+                Some(msg.selector.deadline.map_or(deadline, |d| d.min(deadline)));
+        }
+        let encoded_msg = self.exchange(msg, Correlation::Request, timeout)?;
+
+        match R::decode_with(
+            encoded_msg.selector.uuid,
+            encoded_msg.selector.codec,
+            encoded_msg.payload_data,
+        ) {
+            Ok(payload) => {
+                let mut msg = Message::new(encoded_msg.selector, payload);
+                msg.objects = encoded_msg.objects;
+                msg.memory_regions = encoded_msg.memory_regions;
+                Ok(msg)
+            }
+            Err(Error::TypeUuidNotFound) => Err(RequestError::TypeUuidNotFound),
+            Err(Error::Decode(err)) => Err(RequestError::Recv(RecvError::Decode(err))),
+            Err(_) => unreachable!(),
+        }
+    }
+
+    /// Send a message and wait for the bus controller to report where it was routed.
+    ///
+    /// Like [`request`](EndpointSender::request), the report arrives at the receiving half of this endpoint.
+    pub fn send_confirmed(
+        &self,
+        msg: Message<T>,
+        timeout: Option<Duration>,
+    ) -> Result<Delivery, RequestError> {
+        self.confirm(msg, Correlation::Confirm, timeout)
+    }
+
+    /// Send a reply built with [`Message::reply`] and wait for the bus controller to route it.
+    ///
+    /// Returns `RequestError::LateReply` if the requester stopped waiting for it and
+    /// `RequestError::CallerGone` if the requester left the bus. A reply routed right before the
+    /// requester times out is still dropped by it. Other messages are sent as with [`send`](EndpointSender::send).
+    ///
+    /// The deadline of the request is the requester's wall clock time, lateness is judged by the
+    /// clock of this process, so clock skew between the processes can misclassify a reply.
+    pub fn send_reply(
+        &self,
+        reply: Message<T>,
+        timeout: Option<Duration>,
+    ) -> Result<(), RequestError> {
+        let Some(Correlation::Reply(id)) = reply.selector.correlation else {
+            return Ok(self.send(reply)?);
+        };
+        if reply.selector.is_expired() {
+            return Err(RequestError::LateReply);
+        }
+
+        let deadline = reply.selector.deadline;
+        let correlation = |confirm| Correlation::ConfirmReply { reply: id, confirm };
+        match self.confirm(reply, correlation, timeout)? {
+            Delivery::NoRoute if matches!(deadline, Some(deadline) if deadline <= SystemTime::now()) => {
+                Err(RequestError::LateReply)
+            }
+            Delivery::NoRoute => Err(RequestError::CallerGone),
+            Delivery::Routed { .. } | Delivery::Buffered { .. } => Ok(()),
+        }
+    }
+
+    fn confirm<P: MessageBox>(
+        &self,
+        msg: Message<P>,
+        correlation: impl FnOnce(u64) -> Correlation,
+        timeout: Option<Duration>,
+    ) -> Result<Delivery, RequestError> {
+        let encoded_msg = self.exchange(msg, correlation, timeout)?;
+
+        match Delivery::decode_with(
+            encoded_msg.selector.uuid,
+            encoded_msg.selector.codec,
+            encoded_msg.payload_data,
+        ) {
+            Ok(delivery) => Ok(delivery),
+            Err(Error::TypeUuidNotFound) => Err(RequestError::TypeUuidNotFound),
+            Err(Error::Decode(err)) => Err(RequestError::Recv(RecvError::Decode(err))),
+            Err(_) => unreachable!(),
+        }
+    }
+
+    /// Ask the bus controller which endpoints are on the bus.
+    pub fn bus_info(&self, timeout: Option<Duration>) -> Result<BusInfo, RequestError> {
+        let msg = Message::new(Selector::unicast(LabelOp::True), QueryMessage);
+        let encoded_msg = self.exchange(msg, Correlation::Request, timeout)?;
+
+        match BusInfo::decode_with(
+            encoded_msg.selector.uuid,
+            encoded_msg.selector.codec,
+            encoded_msg.payload_data,
+        ) {
+            Ok(info) => Ok(info),
+            Err(Error::TypeUuidNotFound) => Err(RequestError::TypeUuidNotFound),
+            Err(Error::Decode(err)) => Err(RequestError::Recv(RecvError::Decode(err))),
+            Err(_) => unreachable!(),
+        }
+    }
+
+    /// Send `msg` tagged with a new correlation id and wait for the reply carrying the same id.
+    fn exchange<P: MessageBox>(
+        &self,
+        mut msg: Message<P>,
+        correlation: impl FnOnce(u64) -> Correlation,
+        timeout: Option<Duration>,
+    ) -> Result<EncodedMessage, RequestError> {
+        let id = CORRELATION_ID.fetch_add(1, Ordering::Relaxed);
+        msg.selector.correlation = Some(correlation(id));
+
+        self.inbox.lock().replies.insert(id, Reply::Waiting);
+        let r = self
+            .send_inner(msg, None)
+            .map_err(RequestError::from)
+            .and_then(|_| {
+                self.inbox.wait(
+                    timeout,
+                    |state| match state.replies.get(&id) {
+                        Some(Reply::Waiting) | None => None,
+                        Some(_) => state.replies.remove(&id),
+                    },
+                    |timeout| match recv_encoded(&self.rule, &self.inbox, timeout) {
+                        Err(RecvError::ReaderClosed) => Err(RequestError::ReaderClosed),
+                        r => r.map_err(RequestError::from),
+                    },
+                )
+            });
+        self.inbox.lock().replies.remove(&id);
+
+        match r? {
+            Some(Reply::Ready(encoded_msg)) => Ok(*encoded_msg),
+            Some(_) => Err(RequestError::Disconnect),
+            None => Err(RequestError::Timeout),
         }
     }
 }
@@ -198,199 +525,353 @@ impl<T: MessageBox> EndpointSender<T> {
 // Don't impl Clone
 pub struct EndpointReceiver<R> {
     rule: Arc<RwLock<Rule>>,
+    inbox: Arc<Inbox>,
+    /// The message type of a receiver created by [`split`](EndpointReceiver::split).
+    split: Option<Bytes>,
+    #[cfg(feature = "async")]
+    readiness: async_io::Readiness,
     _maker: PhantomData<R>,
 }
 
 impl<'de, R: MessageBox> EndpointReceiver<R> {
+    /// The id of this endpoint, it changes when the endpoint rejoins the bus.
+    pub fn endpoint_id(&self) -> EndpointID {
+        self.rule.read().unwrap().endpoint_id()
+    }
+
     pub fn recv(&mut self, timeout: Option<Duration>) -> Result<Message<R>, RecvError> {
+        let end = timeout.map(|timeout| Instant::now() + timeout);
+
         loop {
-            let rule = self.rule.read().unwrap();
-            match &*rule {
-                Rule::Client {
-                    endpoint_id: _,
-                    options,
-                    remote,
-                    io_hub,
-                    reader_closed,
-                    im: _,
-                    epoch,
-                } => {
-                    if !*reader_closed && io_hub.is_none() {
-                        let epoch = *epoch;
-                        drop(rule);
+            let encoded_msg = self.wait_encoded(end)?;
 
-                        let mut rule = self.rule.write().unwrap();
-                        match &mut *rule {
-                            Rule::Client {
-                                endpoint_id: _,
-                                options,
-                                remote: _,
-                                io_hub,
-                                reader_closed,
-                                im,
-                                epoch: epoch1,
-                            } => {
-                                if epoch == *epoch1 {
-                                    let reader_closed = *reader_closed;
-
-                                    // Close reader
-                                    drop(io_hub.take());
-
-                                    *rule = Rule::join(
-                                        options.clone(),
-                                        epoch.overflowing_add(1).0,
-                                        im.clone(),
-                                        timeout,
-                                    )?;
-
-                                    if reader_closed {
-                                        rule.reader_close();
-                                    }
-                                }
-
-                                continue;
-                            }
-                            Rule::Server { .. } => continue,
-                        }
-                    }
+            match R::decode_with(
+                encoded_msg.selector.uuid,
+                encoded_msg.selector.codec,
+                encoded_msg.payload_data,
+            ) {
+                Ok(payload) => {
+                    let mut msg = Message::new(encoded_msg.selector, payload);
+                    msg.objects = encoded_msg.objects;
+                    msg.memory_regions = encoded_msg.memory_regions;
+                    break Ok(msg);
+                }
+                Err(Error::TypeUuidNotFound) => {
+                    metrics::increment(&metrics::COUNTERS.type_uuid_not_found);
+                    continue;
+                }
+                Err(Error::Decode(err)) => {
+                    metrics::increment(&metrics::COUNTERS.decode_failures);
+                    break Err(RecvError::Decode(err));
+                }
+                Err(_) => unreachable!(),
+            }
+        }
+    }
 
-                    let mut io_hub_guard = io_hub.as_ref().expect("reader closed").lock().unwrap();
-
-                    match io_hub_guard.recv(timeout, Some(remote)) {
-                        Ok(encoded_msg) => {
-                            if encoded_msg.selector.label_op.validate(&options.label) {
-                                match R::decode(encoded_msg.selector.uuid, encoded_msg.payload_data)
-                                {
-                                    Ok(payload) => {
-                                        let mut msg = Message::new(encoded_msg.selector, payload);
-                                        msg.objects = encoded_msg.objects;
-                                        msg.memory_regions = encoded_msg.memory_regions;
-                                        break Ok(msg);
-                                    }
-                                    Err(Error::TypeUuidNotFound) => {
-                                        continue;
-                                    }
-                                    Err(Error::Decode(err)) => {
-                                        break Err(RecvError::Decode(err));
-                                    }
-                                    Err(_) => unreachable!(),
-                                }
-                            } else {
-                                log::warn!(
-                                    "Unexpected message label_op: {:?}",
-                                    encoded_msg.selector.label_op
-                                );
-                                continue;
-                            }
-                        }
-                        Err(Error::Disconnect) => {
-                            let epoch = *epoch;
-                            drop(io_hub_guard);
-                            drop(rule);
+    /// Like [`recv`](EndpointReceiver::recv), but the payload is left encoded in the received buffer,
+    /// which [`Borrowed::decode`] borrows `&str` and `&[u8]` fields from.
+    ///
+    /// Messages of any type are returned, not only those in `R`.
+    pub fn recv_ref(&mut self, timeout: Option<Duration>) -> Result<Message<Borrowed>, RecvError> {
+        let end = timeout.map(|timeout| Instant::now() + timeout);
+        let mut encoded_msg = self.wait_encoded(end)?;
 
-                            let mut rule = self.rule.write().unwrap();
-                            match &mut *rule {
-                                Rule::Client {
-                                    endpoint_id: _,
-                                    options,
-                                    remote: _,
-                                    io_hub,
-                                    reader_closed,
-                                    im,
-                                    epoch: epoch1,
-                                } => {
-                                    if epoch == *epoch1 {
-                                        let reader_closed = *reader_closed;
-
-                                        // Close reader
-                                        drop(io_hub.take());
-
-                                        *rule = Rule::join(
-                                            options.clone(),
-                                            epoch.overflowing_add(1).0,
-                                            im.clone(),
-                                            timeout,
-                                        )?;
-
-                                        if reader_closed {
-                                            rule.reader_close();
-                                        }
-                                    }
-
-                                    continue;
-                                }
-                                Rule::Server { .. } => continue,
-                            }
-                        }
-                        Err(Error::Timeout) => {
-                            break Err(RecvError::Timeout);
-                        }
-                        Err(_) => unreachable!(),
-                    }
-                }
-                Rule::Server {
-                    endpoint_id: _,
-                    bus_sender: _,
-                    receiver,
-                    im: _,
-                } => {
-                    let receiver = receiver.as_ref().expect("reader closed").lock().unwrap();
-                    break match timeout {
-                        Some(timeout) => match receiver.recv_timeout(timeout) {
-                            Ok(encoded_msg) => {
-                                match R::decode(encoded_msg.selector.uuid, encoded_msg.payload_data)
-                                {
-                                    Ok(payload) => {
-                                        let mut msg = Message::new(encoded_msg.selector, payload);
-                                        msg.objects = encoded_msg.objects;
-                                        msg.memory_regions = encoded_msg.memory_regions;
-                                        Ok(msg)
-                                    }
-                                    Err(Error::TypeUuidNotFound) => {
-                                        continue;
-                                    }
-                                    Err(Error::Decode(err)) => Err(RecvError::Decode(err)),
-                                    Err(_) => unreachable!(),
-                                }
-                            }
-                            Err(RecvTimeoutError::Timeout) => Err(RecvError::Timeout),
-                            Err(_) => unreachable!(),
-                        },
-                        None => {
-                            let encoded_msg = receiver.recv().unwrap();
-                            match R::decode(encoded_msg.selector.uuid, encoded_msg.payload_data) {
-                                Ok(payload) => {
-                                    let mut msg = Message::new(encoded_msg.selector, payload);
-                                    msg.objects = encoded_msg.objects;
-                                    msg.memory_regions = encoded_msg.memory_regions;
-                                    Ok(msg)
-                                }
-                                Err(Error::TypeUuidNotFound) => {
-                                    continue;
-                                }
-                                Err(Error::Decode(err)) => Err(RecvError::Decode(err)),
-                                Err(_) => unreachable!(),
-                            }
-                        }
-                    };
+        Ok(Message {
+            selector: encoded_msg.selector.clone(),
+            objects: mem::take(&mut encoded_msg.objects),
+            memory_regions: mem::take(&mut encoded_msg.memory_regions),
+            payload: Borrowed::new(encoded_msg),
+        })
+    }
+
+    /// Wait for the next message of this receiver until `end`.
+    fn wait_encoded(&self, end: Option<Instant>) -> Result<EncodedMessage, RecvError> {
+        let timeout = end.map(|end| end.saturating_duration_since(Instant::now()));
+
+        match self.inbox.wait(
+            timeout,
+            |state| state.take(self.split),
+            |timeout| recv_encoded(&self.rule, &self.inbox, timeout),
+        ) {
+            Ok(Some(r)) => r,
+            Ok(None) => Err(RecvError::Timeout),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// The number of messages this endpoint discarded because their deadline passed before they
+    /// were received, see [`Selector::deadline`].
+    pub fn expired_count(&self) -> u64 {
+        self.inbox.lock().expired
+    }
+
+    /// Tell the bus controller that a [`reliable`](Selector::reliable) message was handled, so it's
+    /// not delivered again. Does nothing for other messages.
+    ///
+    /// Messages received before this endpoint rejoined the bus can't be acked, they are delivered again.
+    pub fn ack<M>(&self, msg: &Message<M>) {
+        let Some(sequence) = msg.selector.sequence.filter(|_| msg.selector.reliable) else {
+            return;
+        };
+
+        self.rule.read().unwrap().control(AckMessage { sequence });
+    }
+
+    /// Split off a receiver for messages of type `S`, they are no longer delivered to this receiver.
+    ///
+    /// Every receiver has its own queue, fed from the same connection, so different threads can
+    /// consume different message types. The underlying connection is closed once all receivers
+    /// are dropped.
+    pub fn split<S: MessageBox + TypeUuid>(&self) -> EndpointReceiver<S> {
+        self.inbox.split(S::UUID);
+
+        EndpointReceiver {
+            rule: self.rule.clone(),
+            inbox: self.inbox.clone(),
+            split: Some(S::UUID),
+            #[cfg(feature = "async")]
+            readiness: Default::default(),
+            _maker: PhantomData,
+        }
+    }
+
+    /// Receive a message that has already arrived without waiting, `Ok(None)` if there is none.
+    pub fn try_recv(&mut self) -> Result<Option<Message<R>>, RecvError> {
+        match self.recv(Some(Duration::ZERO)) {
+            Ok(msg) => Ok(Some(msg)),
+            Err(RecvError::Timeout) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Wait up to `timeout` for the first message, then take up to `max` messages that have
+    /// already arrived.
+    ///
+    /// An empty `Vec` means nothing arrived before the timeout. An error after the first message
+    /// ends the batch early, it is only returned if nothing was received.
+    pub fn recv_many(
+        &mut self,
+        max: usize,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<Message<R>>, RecvError> {
+        let mut msgs = Vec::new();
+        if max == 0 {
+            return Ok(msgs);
+        }
+
+        match self.recv(timeout) {
+            Ok(msg) => msgs.push(msg),
+            Err(RecvError::Timeout) => return Ok(msgs),
+            Err(err) => return Err(err),
+        }
+
+        while msgs.len() < max {
+            match self.try_recv() {
+                Ok(Some(msg)) => msgs.push(msg),
+                Ok(None) => break,
+                Err(err) => {
+                    log::warn!("recv_many: {err}");
+                    break;
                 }
             }
         }
+
+        Ok(msgs)
     }
 }
 
 impl<R> Drop for EndpointReceiver<R> {
     fn drop(&mut self) {
-        let mut rule = self.rule.write().unwrap();
-        rule.reader_close();
+        if self.inbox.release(self.split) {
+            let mut rule = self.rule.write().unwrap();
+            rule.reader_close();
+        }
+    }
+}
+
+/// Replace the rule of a disconnected client, unless another thread already did it for `epoch`.
+fn rejoin(
+    rule: &RwLock<Rule>,
+    inbox: &Inbox,
+    epoch: u32,
+    timeout: Option<Duration>,
+) -> Result<(), JoinError> {
+    let mut rule = rule.write().unwrap();
+    match &mut *rule {
+        Rule::Client {
+            endpoint_id: _,
+            options,
+            label,
+            remote: _,
+            io_hub,
+            reader_closed,
+            im,
+            epoch: epoch1,
+        } => {
+            if epoch == *epoch1 {
+                let reader_closed = *reader_closed;
+                // Rejoin with the label set since joining
+                let options = Options {
+                    label: label.get_mut().unwrap().clone(),
+                    ..options.clone()
+                };
+
+                // Close reader
+                drop(io_hub.take());
+
+                *rule = Rule::join(options, epoch.overflowing_add(1).0, im.clone(), timeout)?;
+
+                if reader_closed {
+                    rule.reader_close();
+                }
+
+                inbox.disconnect();
+                metrics::increment(&metrics::COUNTERS.rejoins);
+            }
+        }
+        Rule::Server { .. } => {}
+    }
+
+    Ok(())
+}
+
+/// Read one message from the underlying connection, rejoin the bus if disconnected.
+///
+/// `Ok(None)` means nothing arrived before the timeout.
+/// Queue `msg` for the bus controller in this process, waiting for room until `end`.
+fn send_bus(
+    bus_sender: &SyncSender<EncodedMessage>,
+    mut msg: EncodedMessage,
+    end: Option<Instant>,
+) -> Result<(), SendError> {
+    let Some(end) = end else {
+        bus_sender.send(msg).unwrap();
+        return Ok(());
+    };
+
+    loop {
+        match bus_sender.try_send(msg) {
+            Ok(_) => break Ok(()),
+            Err(TrySendError::Full(full)) => {
+                let remain = end.saturating_duration_since(Instant::now());
+                if remain.is_zero() {
+                    break Err(SendError::Timeout);
+                }
+                thread::sleep(remain.min(BUS_RETRY_INTERVAL));
+                msg = full;
+            }
+            Err(TrySendError::Disconnected(_)) => unreachable!(),
+        }
+    }
+}
+
+fn recv_encoded(
+    rule: &RwLock<Rule>,
+    inbox: &Inbox,
+    timeout: Option<Duration>,
+) -> Result<Option<EncodedMessage>, RecvError> {
+    loop {
+        let guard = rule.read().unwrap();
+        match &*guard {
+            Rule::Client {
+                endpoint_id: _,
+                options: _,
+                label,
+                remote,
+                io_hub,
+                reader_closed,
+                im: _,
+                epoch,
+            } => {
+                if *reader_closed {
+                    break Err(RecvError::ReaderClosed);
+                }
+
+                if io_hub.is_none() {
+                    let epoch = *epoch;
+                    drop(guard);
+
+                    rejoin(rule, inbox, epoch, timeout)?;
+                    continue;
+                }
+
+                let mut io_hub_guard = io_hub.as_ref().expect("reader closed").lock().unwrap();
+
+                match io_hub_guard.recv(timeout, Some(remote)) {
+                    Ok(mut encoded_msg) => {
+                        if let Err(err) = encoded_msg.unspill() {
+                            log::error!("unspill: {:?}", err);
+                            continue;
+                        }
+
+                        if encoded_msg.selector.target.is_some()
+                            || encoded_msg
+                                .selector
+                                .label_op
+                                .validate(&label.lock().unwrap())
+                        {
+                            break Ok(Some(encoded_msg));
+                        } else {
+                            log::warn!(
+                                "Unexpected message label_op: {:?}",
+                                encoded_msg.selector.label_op
+                            );
+                            continue;
+                        }
+                    }
+                    Err(Error::Disconnect) => {
+                        let epoch = *epoch;
+                        drop(io_hub_guard);
+                        drop(guard);
+
+                        rejoin(rule, inbox, epoch, timeout)?;
+                        continue;
+                    }
+                    Err(Error::Timeout) => {
+                        break Ok(None);
+                    }
+                    Err(_) => unreachable!(),
+                }
+            }
+            Rule::Server {
+                endpoint_id: _,
+                bus_sender: _,
+                receiver,
+                depth,
+                im: _,
+                ..
+            } => {
+                let Some(receiver) = receiver else {
+                    break Err(RecvError::ReaderClosed);
+                };
+                let receiver = receiver.lock().unwrap();
+                let mut encoded_msg = match timeout {
+                    Some(timeout) => match receiver.recv_timeout(timeout) {
+                        Ok(encoded_msg) => encoded_msg,
+                        Err(RecvTimeoutError::Timeout) => break Ok(None),
+                        Err(_) => unreachable!(),
+                    },
+                    None => receiver.recv().unwrap(),
+                };
+                depth.fetch_sub(1, Ordering::Relaxed);
+
+                if let Err(err) = encoded_msg.unspill() {
+                    log::error!("unspill: {:?}", err);
+                    continue;
+                }
+                break Ok(Some(encoded_msg));
+            }
+        }
     }
 }
 
 enum Rule {
     Client {
-        #[allow(dead_code)]
         endpoint_id: EndpointID,
         options: Options,
+        /// The current label, it changes with `EndpointSender::set_label` while the rule is shared.
+        label: Mutex<Label>,
         remote: Remote,
         io_hub: Option<Mutex<IoHub>>,
         reader_closed: bool,
@@ -398,11 +879,18 @@ enum Rule {
         epoch: u32,
     },
     Server {
-        #[allow(dead_code)]
         endpoint_id: EndpointID,
-        bus_sender: Mutex<Sender<EncodedMessage>>,
-        receiver: Option<Mutex<Receiver<EncodedMessage>>>,
+        options: Options,
+        label: Mutex<Label>,
+        /// Bounded by the queue capacity, a full channel holds up senders in this process.
+        bus_sender: Mutex<SyncSender<EncodedMessage>>,
+        receiver: Option<Arc<Mutex<Receiver<EncodedMessage>>>>,
+        /// Messages in `receiver`, reported as the queue depth of this endpoint.
+        depth: Arc<AtomicUsize>,
         im: Arc<IoMultiplexing>,
+        /// Woken by the bus controller when a message is delivered to `receiver`.
+        #[cfg(feature = "async")]
+        notify: Arc<IoMultiplexing>,
     },
 }
 
@@ -414,6 +902,7 @@ impl Rule {
         timeout: Option<Duration>,
     ) -> Result<Self, JoinError> {
         let end = timeout.map(|timeout| Instant::now() + timeout);
+        let configure = ConfigureMessage::new(&options);
 
         macro_rules! wait {
             () => {
@@ -445,6 +934,7 @@ impl Rule {
                 Ok((io_hub, remote, endpoint_id)) => {
                     let rule = Rule::Client {
                         endpoint_id,
+                        label: Mutex::new(options.label.clone()),
                         options,
                         remote,
                         io_hub: Some(Mutex::new(io_hub)),
@@ -461,28 +951,50 @@ impl Rule {
                         continue;
                     }
 
-                    let r = register(&options.identifier, im.clone());
+                    let r = register(
+                        &options.identifier,
+                        options.queue_capacity.get(),
+                        im.clone(),
+                    );
 
                     match r {
                         Ok((io_hub, bus_sender, endpoint_id)) => {
-                            let (sender, receiver) = mpsc::channel::<EncodedMessage>();
+                            let (sender, receiver) =
+                                mpsc::sync_channel::<EncodedMessage>(options.queue_capacity.get());
+                            let receiver = Arc::new(Mutex::new(receiver));
+                            let depth = Arc::new(AtomicUsize::new(0));
 
                             let im = io_hub.io_multiplexing();
 
                             let bus_controller = BusController::new(
                                 endpoint_id,
-                                options.label,
-                                options.token,
+                                options.label.clone(),
+                                options.token.clone(),
                                 sender,
+                                Arc::downgrade(&receiver),
+                                depth.clone(),
                                 io_hub,
                             );
+                            let bus_controller = match &options.spool {
+                                Some(dir) => bus_controller.with_spool(dir, &options.identifier),
+                                None => bus_controller,
+                            };
+                            #[cfg(feature = "async")]
+                            let notify = Arc::new(IoMultiplexing::new());
+                            #[cfg(feature = "async")]
+                            let bus_controller = bus_controller.with_notify(notify.clone());
                             bus_controller.run();
 
                             let rule = Rule::Server {
                                 endpoint_id,
+                                label: Mutex::new(options.label.clone()),
+                                options,
                                 bus_sender: Mutex::new(bus_sender),
-                                receiver: Some(Mutex::new(receiver)),
+                                receiver: Some(receiver),
+                                depth,
                                 im,
+                                #[cfg(feature = "async")]
+                                notify,
                             };
                             break rule;
                         }
@@ -529,11 +1041,65 @@ impl Rule {
             }
         };
 
+        rule.control(configure);
+
         Ok(rule)
     }
 }
 
 impl Rule {
+    fn endpoint_id(&self) -> EndpointID {
+        match self {
+            Rule::Client { endpoint_id, .. } | Rule::Server { endpoint_id, .. } => *endpoint_id,
+        }
+    }
+
+    /// Pass `payload` from this endpoint to the bus controller, e.g. a [`ConfigureMessage`].
+    fn control<T: MessageBox>(&self, payload: T) {
+        let mut msg = Message::new(Selector::unicast(LabelOp::True), payload);
+        msg.selector.source = Some(self.endpoint_id());
+        let mut msg = msg.into_encoded();
+
+        match self {
+            Rule::Client { remote, .. } => {
+                // Configured again on rejoin, unacked messages are routed again
+                if let Err(err) = msg.send(remote) {
+                    log::error!("control: {:?}", err);
+                }
+            }
+            Rule::Server { bus_sender, im, .. } => {
+                bus_sender.lock().unwrap().send(msg).unwrap();
+                im.wake();
+            }
+        }
+    }
+
+    fn options(&self) -> &Options {
+        match self {
+            Rule::Client { options, .. } | Rule::Server { options, .. } => options,
+        }
+    }
+
+    /// Change the label of this endpoint, the bus controller routes by it from now on.
+    ///
+    /// Only needs a shared rule, a receiver may hold it while waiting for a message.
+    fn update_label(&self, f: impl FnOnce(&mut Label)) {
+        let (options, label) = match self {
+            Rule::Client { options, label, .. } | Rule::Server { options, label, .. } => {
+                (options, label)
+            }
+        };
+        // Held until sent, so concurrent changes reach the bus controller in order
+        let mut label = label.lock().unwrap();
+        f(&mut label);
+
+        let configure = ConfigureMessage {
+            label: label.clone(),
+            ..ConfigureMessage::new(options)
+        };
+        self.control(configure);
+    }
+
     fn reader_close(&mut self) {
         match self {
             Rule::Client {
@@ -602,3 +1168,311 @@ pub fn version() -> Version {
 pub fn version_pre() -> String {
     VERSION_PRE.to_owned()
 }
+
+/// A snapshot of the counters of this process.
+pub fn metrics() -> Metrics {
+    metrics::COUNTERS.snapshot()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{label, Options, Selector};
+    use std::time::Duration;
+
+    /// Join `identifier` with `label`, the first endpoint of a bus is its controller.
+    fn join(
+        identifier: &str,
+        label: crate::Label,
+    ) -> (
+        super::EndpointSender<String>,
+        super::EndpointReceiver<String>,
+    ) {
+        super::join::<String, String>(
+            Options::new(identifier, label, ""),
+            Some(Duration::from_secs(5)),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn local_queue_full() {
+        let identifier = format!("com.ipmb.test.local_queue_full.{}", std::process::id());
+        // The controller endpoint never reads
+        let (_controller, _controller_receiver) = join(&identifier, label!("all"));
+        let (sender, mut receiver) = join(&identifier, label!("all", "client"));
+
+        for i in 0..200 {
+            sender
+                .send(crate::Message::new(
+                    Selector::multicast("all"),
+                    i.to_string(),
+                ))
+                .unwrap();
+        }
+        sender
+            .send(crate::Message::new(
+                Selector::unicast("client"),
+                "done".to_string(),
+            ))
+            .unwrap();
+
+        loop {
+            let msg = receiver.recv(Some(Duration::from_secs(5))).unwrap();
+            if msg.payload == "done" {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn spill_threshold_clamped() {
+        let identifier = format!("com.ipmb.test.spill_threshold.{}", std::process::id());
+        let (_controller, _controller_receiver) = join(&identifier, label!("controller"));
+        let mut options = Options::new(&identifier, label!("client"), "");
+        options.spill_threshold = usize::MAX;
+        let (sender, mut receiver) =
+            super::join::<String, String>(options, Some(Duration::from_secs(5))).unwrap();
+
+        let payload = "x".repeat(128 << 10);
+        sender
+            .send(crate::Message::new(
+                Selector::unicast("client"),
+                payload.clone(),
+            ))
+            .unwrap();
+
+        let msg = receiver.recv(Some(Duration::from_secs(5))).unwrap();
+        assert_eq!(msg.payload, payload);
+    }
+
+    #[test]
+    fn set_label_while_receiving() {
+        let identifier = format!("com.ipmb.test.set_label.{}", std::process::id());
+        let (controller, _controller_receiver) = join(&identifier, label!("controller"));
+        let (sender, mut receiver) = join(&identifier, label!("before"));
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let parked = std::thread::spawn(move || {
+            let msg = receiver.recv(None).unwrap();
+            tx.send(msg.payload).unwrap();
+        });
+        std::thread::sleep(Duration::from_millis(100));
+
+        // Doesn't wait for the parked receiver
+        let (done, relabeled) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            sender.set_label(label!("after"));
+            done.send(sender).unwrap();
+        });
+        let _sender = relabeled.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        let mut selector = Selector::unicast("after");
+        selector.ttl = Duration::from_secs(5);
+        controller
+            .send(crate::Message::new(selector, "hello".to_string()))
+            .unwrap();
+
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), "hello");
+        parked.join().unwrap();
+    }
+
+    #[test]
+    fn block_holds_sender() {
+        let identifier = format!("com.ipmb.test.block_holds_sender.{}", std::process::id());
+        let (controller, _controller_receiver) = join(&identifier, label!("controller"));
+
+        let mut options = Options::new(&identifier, label!("slow"), "");
+        options.queue_capacity = std::num::NonZeroUsize::new(1).unwrap();
+        options.overflow = crate::Overflow::Block;
+        // Doesn't read until the producer is held
+        let (_slow, mut slow_receiver) =
+            super::join::<String, String>(options, Some(Duration::from_secs(5))).unwrap();
+        let (producer, _producer_receiver) = join(&identifier, label!("producer"));
+        let (_other, mut other_receiver) = join(&identifier, label!("other"));
+
+        let mut full = false;
+        for i in 0..100_000 {
+            let msg = crate::Message::new(Selector::unicast("slow"), i.to_string());
+            match producer.send_timeout(msg, Duration::from_millis(100)) {
+                Ok(_) => {}
+                Err(crate::SendError::Timeout) => {
+                    full = true;
+                    break;
+                }
+                Err(err) => panic!("{err:?}"),
+            }
+        }
+        assert!(full, "the producer was never held");
+
+        // The bus controller still routes for everyone else
+        controller
+            .send(crate::Message::new(
+                Selector::unicast("other"),
+                "hello".to_string(),
+            ))
+            .unwrap();
+        let msg = other_receiver.recv(Some(Duration::from_secs(5))).unwrap();
+        assert_eq!(msg.payload, "hello");
+
+        // Released once the endpoint reads
+        std::thread::spawn(move || while slow_receiver.recv(None).is_ok() {});
+        producer
+            .send_timeout(
+                crate::Message::new(Selector::unicast("slow"), "again".to_string()),
+                Duration::from_secs(5),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn monitor_not_routed() {
+        let identifier = format!("com.ipmb.test.monitor_not_routed.{}", std::process::id());
+        let (controller, _controller_receiver) = join(&identifier, label!("controller"));
+
+        let mut options = Options::new(&identifier, label!("target"), "");
+        options.monitor = true;
+        let (monitor, _monitor_receiver) =
+            super::join::<String, String>(options, Some(Duration::from_secs(5))).unwrap();
+        // Answered after the bus controller took the options of the monitor
+        monitor.bus_info(Some(Duration::from_secs(5))).unwrap();
+
+        let (_target, mut target_receiver) = join(&identifier, label!("target"));
+        controller
+            .send(crate::Message::new(
+                Selector::unicast("target"),
+                "hello".to_string(),
+            ))
+            .unwrap();
+
+        let msg = target_receiver.recv(Some(Duration::from_secs(5))).unwrap();
+        assert_eq!(msg.payload, "hello");
+    }
+
+    #[test]
+    fn monitor_taps() {
+        let identifier = format!("com.ipmb.test.monitor_taps.{}", std::process::id());
+        let (controller, _controller_receiver) = join(&identifier, label!("controller"));
+
+        let monitors: Vec<_> = (0..2)
+            .map(|_| {
+                let mut options = Options::new(&identifier, label!("monitor"), "");
+                options.monitor = true;
+                let (monitor, monitor_receiver) =
+                    super::join::<String, crate::Tap>(options, Some(Duration::from_secs(5)))
+                        .unwrap();
+                // Answered after the bus controller took the options of the monitor
+                monitor.bus_info(Some(Duration::from_secs(5))).unwrap();
+                (monitor, monitor_receiver)
+            })
+            .collect();
+
+        let (_target, _target_receiver) = join(&identifier, label!("target"));
+        for payload in ["small".to_string(), "x".repeat(64 << 10)] {
+            controller
+                .send(crate::Message::new(Selector::unicast("target"), payload))
+                .unwrap();
+        }
+
+        for (_monitor, mut monitor_receiver) in monitors {
+            let small = monitor_receiver
+                .recv(Some(Duration::from_secs(5)))
+                .unwrap()
+                .payload;
+            assert!(!small.spilled);
+            assert_eq!(crate::decode::<String>(&small.payload).unwrap(), "small");
+
+            let large = monitor_receiver
+                .recv(Some(Duration::from_secs(5)))
+                .unwrap()
+                .payload;
+            assert!(large.spilled);
+            assert!(large.payload.is_empty());
+        }
+    }
+
+    #[test]
+    fn targeted_not_redelivered() {
+        let identifier = format!(
+            "com.ipmb.test.targeted_not_redelivered.{}",
+            std::process::id()
+        );
+        let (controller, _controller_receiver) = join(&identifier, label!("controller"));
+        let (client, mut client_receiver) = join(&identifier, label!("client"));
+
+        let dropped = crate::metrics().unacked_dropped;
+        let mut selector = Selector::to_endpoint(client.endpoint_id());
+        selector.reliable = true;
+        controller
+            .send(crate::Message::new(selector, "hello".to_string()))
+            .unwrap();
+
+        client_receiver.recv(Some(Duration::from_secs(5))).unwrap();
+        // The client leaves without acking
+        drop((client, client_receiver));
+        for _ in 0..50 {
+            std::thread::sleep(Duration::from_millis(100));
+            if crate::metrics().unacked_dropped > dropped {
+                return;
+            }
+        }
+        panic!("targeted message not dropped");
+    }
+
+    #[test]
+    fn buffered_deadline() {
+        let identifier = format!("com.ipmb.test.buffered_deadline.{}", std::process::id());
+        let (_controller, _controller_receiver) = join(&identifier, label!("controller"));
+        let (sender, _receiver) = join(&identifier, label!("client"));
+
+        let mut selector = Selector::unicast("nobody");
+        selector.ttl = Duration::from_secs(60);
+        selector.expire_after(Duration::from_millis(100));
+        sender
+            .send(crate::Message::new(selector, "late".to_string()))
+            .unwrap();
+
+        // The message is dropped at its deadline although its ttl hasn't run out
+        for _ in 0..50 {
+            std::thread::sleep(Duration::from_millis(100));
+            let info = sender.bus_info(Some(Duration::from_secs(5))).unwrap();
+            if info.buffered.is_empty() {
+                return;
+            }
+        }
+        panic!("message buffered past its deadline");
+    }
+
+    #[test]
+    fn controller_queue_depth() {
+        let identifier = format!("com.ipmb.test.queue_depth.{}", std::process::id());
+        let (_controller, mut controller_receiver) = join(&identifier, label!("controller"));
+        let (sender, _receiver) = join(&identifier, label!("client"));
+
+        for i in 0..3 {
+            sender
+                .send(crate::Message::new(
+                    Selector::unicast("controller"),
+                    i.to_string(),
+                ))
+                .unwrap();
+        }
+
+        let depth = |expected| {
+            for _ in 0..50 {
+                let info = sender.bus_info(Some(Duration::from_secs(5))).unwrap();
+                if info.controller.queue_depth == expected {
+                    return;
+                }
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            panic!("queue depth of the controller isn't {expected}");
+        };
+        depth(3);
+
+        controller_receiver
+            .recv(Some(Duration::from_secs(5)))
+            .unwrap();
+        // The receiver reads everything that arrived, and keeps the rest in its own queue
+        depth(0);
+    }
+}
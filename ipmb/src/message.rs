@@ -1,5 +1,15 @@
-use crate::{EndpointID, Error, Label, MemoryRegion, Object, Selector, Version};
+use crate::{
+    codec::{self, Bincode, Codec},
+    platform::EncodedMessage,
+    Correlation, EndpointID, Error, Label, LabelOp, MemoryRegion, Object, Options, Overflow,
+    Priority, Selector, SelectorMode, SendError, Sequence, TraceContext, TraceGuard, Version,
+};
 use serde::{Deserialize, Serialize};
+use std::{
+    cell::Cell,
+    num::NonZeroUsize,
+    time::{Duration, SystemTime},
+};
 use type_uuid::{Bytes, TypeUuid};
 
 pub struct Message<T> {
@@ -12,6 +22,7 @@ pub struct Message<T> {
 impl<T: MessageBox> Message<T> {
     pub fn new(mut selector: Selector, payload: T) -> Self {
         selector.uuid = payload.uuid();
+        selector.codec = payload.codec();
 
         Self {
             selector,
@@ -22,14 +33,175 @@ impl<T: MessageBox> Message<T> {
     }
 }
 
+impl<T: MessageBox> Message<T> {
+    /// Encode the payload, which is moved into a new memory region if it's larger than `threshold`.
+    pub(crate) fn spill(self, threshold: usize) -> Result<Message<EncodedPayload>, SendError> {
+        let mut data = self.payload.encode().unwrap();
+        let mut selector = self.selector;
+        let mut memory_regions = self.memory_regions;
+
+        if data.len() > threshold {
+            let Some(mut region) = MemoryRegion::new(data.len()) else {
+                log::error!("spill: memory region not created");
+                return Err(SendError::Spill);
+            };
+            match region.map(..) {
+                Ok(buffer) => buffer.copy_from_slice(&data),
+                Err(err) => {
+                    log::error!("spill: {:?}", err);
+                    return Err(SendError::Spill);
+                }
+            }
+            data = vec![];
+            memory_regions.push(region);
+            selector.spilled = true;
+        }
+
+        Ok(Message {
+            selector,
+            payload: EncodedPayload::new(data),
+            objects: self.objects,
+            memory_regions,
+        })
+    }
+}
+
+impl<T> Message<T> {
+    /// The endpoint that sent the message.
+    pub fn source(&self) -> Option<EndpointID> {
+        self.selector.source
+    }
+
+    /// The number of the message among those its sender sent to the same destination,
+    /// `None` for replies and messages of the bus controller.
+    pub fn sequence(&self) -> Option<Sequence> {
+        self.selector.sequence
+    }
+
+    /// How many times the bus controller delivered the message again because an endpoint left
+    /// without acking it, see [`Selector::reliable`].
+    pub fn redelivered(&self) -> u32 {
+        self.selector.redelivered
+    }
+
+    /// The trace context the message was sent with.
+    pub fn trace(&self) -> Option<&TraceContext> {
+        self.selector.trace.as_ref()
+    }
+
+    /// Continue the trace of the message on this thread, messages sent until the guard is dropped
+    /// belong to it. With the `tracing` feature, the guard also enters an `ipmb.recv` span.
+    ///
+    /// Returns `None` if the message was sent without a trace context.
+    pub fn enter_trace(&self) -> Option<TraceGuard> {
+        crate::trace::enter_received(&self.selector)
+    }
+
+    /// Whether the message was sent with [`request`](crate::EndpointSender::request).
+    pub fn is_request(&self) -> bool {
+        matches!(self.selector.correlation, Some(Correlation::Request(_)))
+    }
+
+    /// Build the reply to a request, which is routed back to the requesting endpoint only.
+    /// Returns `None` if the message is not a request.
+    ///
+    /// The reply expires when the requester stops waiting for it.
+    pub fn reply<P: MessageBox>(&self, payload: P) -> Option<Message<P>> {
+        let Some(Correlation::Request(id)) = self.selector.correlation else {
+            return None;
+        };
+
+        let mut selector = Selector::to_endpoint(self.selector.source?);
+        selector.correlation = Some(Correlation::Reply(id));
+        selector.deadline = self.selector.deadline;
+
+        Some(Message::new(selector, payload))
+    }
+}
+
+/// A payload left encoded in the received buffer, see [`recv_ref`](crate::EndpointReceiver::recv_ref).
+pub struct Borrowed {
+    encoded_msg: EncodedMessage,
+}
+
+impl Borrowed {
+    pub(crate) fn new(encoded_msg: EncodedMessage) -> Self {
+        Self { encoded_msg }
+    }
+
+    pub fn uuid(&self) -> Bytes {
+        self.encoded_msg.selector.uuid
+    }
+
+    /// The encoded payload, for formats that are read in place instead of decoded. ipmb has no such
+    /// codec itself.
+    pub fn data(&self) -> &[u8] {
+        self.encoded_msg.payload_data
+    }
+
+    /// Decode the payload, `&str` and `&[u8]` fields of `T` borrow from the received buffer.
+    ///
+    /// Returns `Error::TypeUuidNotFound` if the payload is not a `T`.
+    pub fn decode<'a, T: TypeUuid + Deserialize<'a>>(&'a self) -> Result<T, Error> {
+        if self.uuid() != T::UUID {
+            return Err(Error::TypeUuidNotFound);
+        }
+
+        codec::decode_borrowed(self.encoded_msg.selector.codec, self.data())
+    }
+}
+
 pub trait MessageBox: Send + 'static {
     fn decode(uuid: Bytes, data: &[u8]) -> Result<Self, Error>
     where
         Self: Sized;
 
+    /// Decode a payload encoded with the codec of id `codec`, which may not be the codec of `encode`.
+    /// By default only payloads encoded by [`Bincode`] are decoded with `decode`.
+    fn decode_with(uuid: Bytes, codec: u8, data: &[u8]) -> Result<Self, Error>
+    where
+        Self: Sized,
+    {
+        if codec == Bincode::ID {
+            Self::decode(uuid, data)
+        } else {
+            Err(codec::not_found(codec))
+        }
+    }
+
     fn encode(&self) -> Result<Vec<u8>, Error>;
 
     fn uuid(&self) -> Bytes;
+
+    /// The id of the [`Codec`] used by `encode`.
+    fn codec(&self) -> u8 {
+        Bincode::ID
+    }
+}
+
+/// A payload encoded by [`Message::spill`], the selector keeps its uuid and codec.
+///
+/// The bytes are moved out by the first `encode`, which is the one building the packet.
+pub(crate) struct EncodedPayload(Cell<Vec<u8>>);
+
+impl EncodedPayload {
+    pub fn new(data: Vec<u8>) -> Self {
+        Self(Cell::new(data))
+    }
+}
+
+impl MessageBox for EncodedPayload {
+    fn decode(_uuid: Bytes, _data: &[u8]) -> Result<Self, Error> {
+        Err(Error::TypeUuidNotFound)
+    }
+
+    fn encode(&self) -> Result<Vec<u8>, Error> {
+        Ok(self.0.take())
+    }
+
+    fn uuid(&self) -> Bytes {
+        [0; 16]
+    }
 }
 
 /// A predefined message type.
@@ -65,6 +237,146 @@ pub enum ConnectMessageAck {
     ErrToken,
 }
 
+/// A predefined message type, reports where a message sent with
+/// [`send_confirmed`](crate::EndpointSender::send_confirmed) was routed.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize, TypeUuid)]
+#[uuid = "0f0093d8-794f-4316-90f7-22adfa03dc91"]
+pub enum Delivery {
+    /// Delivered to `receivers` endpoints.
+    Routed { receivers: usize },
+    /// No endpoint matched, the bus controller keeps the message for `ttl` in case one joins.
+    Buffered { ttl: Duration },
+    /// No endpoint matched and the message was dropped.
+    NoRoute,
+}
+
+/// Asks the bus controller for a [`BusInfo`].
+#[derive(Debug, Serialize, Deserialize, TypeUuid)]
+#[uuid = "7c1e5a0f-3b8d-4e62-a4f9-2d6b0c8e91f3"]
+pub struct QueryMessage;
+
+/// A predefined message type, what the bus controller knows about the bus, see
+/// [`bus_info`](crate::EndpointSender::bus_info).
+#[derive(Debug, Clone, Serialize, Deserialize, TypeUuid)]
+#[uuid = "e4a2d6b9-81c3-4f57-9e0a-5b3c7d2f8a16"]
+pub struct BusInfo {
+    /// The endpoint in the process of the bus controller.
+    pub controller: EndpointInfo,
+    /// The other endpoints on the bus.
+    pub endpoints: Vec<EndpointInfo>,
+    /// The number of messages buffered for their ttl, by label op.
+    pub buffered: Vec<(LabelOp, usize)>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EndpointInfo {
+    pub id: EndpointID,
+    pub label: Label,
+    /// The process of the endpoint, `None` where the platform doesn't report it.
+    pub pid: Option<u32>,
+    pub uid: Option<u32>,
+    /// When the endpoint joined the bus, or when the bus controller started.
+    pub connected_at: SystemTime,
+    /// Messages waiting for the endpoint. For the endpoint in the bus controller's process those its
+    /// receiver hasn't read from the channel yet, for the others those the bus controller holds
+    /// because the endpoint doesn't read fast enough, not counting what the platform buffers.
+    pub queue_depth: usize,
+}
+
+/// Options of the source endpoint applied by the bus controller, sent after joining.
+#[derive(Debug, Serialize, Deserialize, TypeUuid)]
+#[uuid = "43b9d7d4-079d-4d2b-86d6-9f098cd70a67"]
+pub struct ConfigureMessage {
+    pub label: Label,
+    pub membership: Option<LabelOp>,
+    pub queue_capacity: NonZeroUsize,
+    pub overflow: Overflow,
+    pub monitor: bool,
+}
+
+impl ConfigureMessage {
+    pub fn new(options: &Options) -> Self {
+        Self {
+            label: options.label.clone(),
+            membership: options.membership.clone(),
+            queue_capacity: options.queue_capacity,
+            overflow: options.overflow,
+            monitor: options.monitor,
+        }
+    }
+}
+
+/// The source endpoint has handled a [`reliable`](Selector::reliable) message.
+#[derive(Debug, Serialize, Deserialize, TypeUuid)]
+#[uuid = "b0f1c6a2-5d4e-4f0b-9a77-3c2e8d1f6a45"]
+pub struct AckMessage {
+    pub sequence: Sequence,
+}
+
+/// A predefined message type, delivered to endpoints joined with [`Options::membership`](crate::Options::membership).
+#[derive(Debug, Clone, Serialize, Deserialize, TypeUuid)]
+#[uuid = "460e776a-d6a7-4e68-b33f-6c7ee0b24cdc"]
+pub enum MembershipEvent {
+    EndpointJoined {
+        id: EndpointID,
+        label: Label,
+    },
+    EndpointLeft {
+        id: EndpointID,
+        label: Label,
+        reason: LeaveReason,
+    },
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum LeaveReason {
+    /// A message could not be delivered to the endpoint.
+    Disconnect,
+    /// The endpoint was found dead by the periodic reachability check.
+    Unreachable,
+    /// The endpoint changed its label, which no longer matches.
+    Relabel,
+}
+
+/// A predefined message type, a copy of a message the bus controller handled, delivered to endpoints
+/// joined with [`Options::monitor`](crate::Options::monitor).
+#[derive(Debug, Clone, Serialize, Deserialize, TypeUuid)]
+#[uuid = "5d3f8b21-6c4a-4e9d-b7f2-0a1e9c3d5b68"]
+pub struct Tap {
+    pub source: Option<EndpointID>,
+    pub label_op: LabelOp,
+    pub mode: SelectorMode,
+    /// Set for messages addressed with [`Selector::to_endpoint`].
+    pub target: Option<EndpointID>,
+    pub priority: Priority,
+    pub sequence: Option<Sequence>,
+    /// The type uuid of the payload, decode it with [`MessageBox::decode`].
+    pub uuid: Bytes,
+    /// The size of the payload in bytes, objects and memory regions aren't copied.
+    pub size: usize,
+    #[serde(with = "serde_bytes")]
+    pub payload: Vec<u8>,
+    /// The payload was in a memory region, see [`Options::spill_threshold`](crate::Options::spill_threshold),
+    /// so `payload` is empty.
+    pub spilled: bool,
+    pub routing: Routing,
+}
+
+/// What the bus controller did with the message of a [`Tap`].
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Routing {
+    /// Delivered to these endpoints.
+    Routed { receivers: Vec<EndpointID> },
+    /// No endpoint matched, the bus controller keeps the message for `ttl` in case one joins.
+    Buffered { ttl: Duration },
+    /// No endpoint matched and the message was dropped.
+    NoRoute,
+    /// The deadline of the message passed, or its ttl while it was buffered.
+    Expired,
+    /// The message buffer was full and the message was dropped to make room.
+    Evicted,
+}
+
 impl<T: TypeUuid + Serialize + for<'de> Deserialize<'de> + Send + 'static> MessageBox for T {
     fn decode(uuid: Bytes, data: &[u8]) -> Result<Self, Error>
     where
@@ -77,6 +389,17 @@ impl<T: TypeUuid + Serialize + for<'de> Deserialize<'de> + Send + 'static> Messa
         }
     }
 
+    fn decode_with(uuid: Bytes, codec: u8, data: &[u8]) -> Result<Self, Error>
+    where
+        Self: Sized,
+    {
+        if uuid == T::UUID {
+            codec::decode::<Bincode, _>(codec, data)
+        } else {
+            Err(Error::TypeUuidNotFound)
+        }
+    }
+
     fn encode(&self) -> Result<Vec<u8>, Error> {
         crate::encode(self)
     }
@@ -0,0 +1,254 @@
+use crate::{EndpointID, Label};
+use once_cell::sync::Lazy;
+use std::sync::{
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+    Mutex,
+};
+
+/// Counters of the endpoints and the bus controller in this process, see [`metrics`](crate::metrics).
+///
+/// Routing is counted by the bus controller, so only the process running it has routes.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    /// Messages and bytes routed to each endpoint on the bus.
+    pub routes: Vec<RouteMetrics>,
+    /// Unicast messages no endpoint matched.
+    pub unicast_misses: u64,
+    /// Messages the bus controller buffered for their [`ttl`](crate::Selector::ttl).
+    pub buffered: u64,
+    /// Messages dropped because their deadline or ttl passed.
+    pub expired: u64,
+    /// How many times an endpoint rejoined the bus after being disconnected.
+    pub rejoins: u64,
+    /// Messages a receiver failed to decode.
+    pub decode_failures: u64,
+    /// Messages skipped by a receiver because their type is not in the message box.
+    pub type_uuid_not_found: u64,
+    /// Reliable messages the bus controller stopped tracking before they were acked, because too
+    /// many were waiting or because they were sent with [`to_endpoint`](crate::Selector::to_endpoint)
+    /// to an endpoint that left. They are not redelivered.
+    pub unacked_dropped: u64,
+    /// Memory regions alive in this process, including those pooled by a
+    /// [`MemoryRegistry`](crate::MemoryRegistry).
+    pub memory_regions: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct RouteMetrics {
+    pub endpoint: EndpointID,
+    pub label: Label,
+    pub messages: u64,
+    /// Payload bytes, memory regions are not counted.
+    pub bytes: u64,
+}
+
+#[derive(Default)]
+pub(crate) struct Counters {
+    routes: Mutex<Vec<RouteMetrics>>,
+    pub unicast_misses: AtomicU64,
+    pub buffered: AtomicU64,
+    pub expired: AtomicU64,
+    pub rejoins: AtomicU64,
+    pub decode_failures: AtomicU64,
+    pub type_uuid_not_found: AtomicU64,
+    pub unacked_dropped: AtomicU64,
+    pub memory_regions: AtomicUsize,
+}
+
+pub(crate) static COUNTERS: Lazy<Counters> = Lazy::new(Default::default);
+
+pub(crate) fn increment(counter: &AtomicU64) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+impl Counters {
+    pub fn route(&self, endpoint: EndpointID, label: &Label, bytes: usize) {
+        let mut routes = self.routes.lock().unwrap();
+        match routes.iter_mut().find(|route| route.endpoint == endpoint) {
+            Some(route) => {
+                if route.label != *label {
+                    route.label = label.clone();
+                }
+                route.messages += 1;
+                route.bytes += bytes as u64;
+            }
+            None => routes.push(RouteMetrics {
+                endpoint,
+                label: label.clone(),
+                messages: 1,
+                bytes: bytes as _,
+            }),
+        }
+    }
+
+    /// The endpoint left the bus.
+    pub fn forget(&self, endpoint: EndpointID) {
+        self.routes
+            .lock()
+            .unwrap()
+            .retain(|route| route.endpoint != endpoint);
+    }
+
+    pub fn snapshot(&self) -> Metrics {
+        Metrics {
+            routes: self.routes.lock().unwrap().clone(),
+            unicast_misses: self.unicast_misses.load(Ordering::Relaxed),
+            buffered: self.buffered.load(Ordering::Relaxed),
+            expired: self.expired.load(Ordering::Relaxed),
+            rejoins: self.rejoins.load(Ordering::Relaxed),
+            decode_failures: self.decode_failures.load(Ordering::Relaxed),
+            type_uuid_not_found: self.type_uuid_not_found.load(Ordering::Relaxed),
+            unacked_dropped: self.unacked_dropped.load(Ordering::Relaxed),
+            memory_regions: self.memory_regions.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(feature = "prometheus")]
+impl Metrics {
+    /// Format in the Prometheus text exposition format, metric names are prefixed with `ipmb_`.
+    pub fn to_prometheus(&self) -> String {
+        let mut s = String::new();
+
+        let route_labels = |route: &RouteMetrics| {
+            let label = route.label.iter().collect::<Vec<_>>().join(",");
+            format!(
+                "endpoint=\"{}\",label=\"{}\"",
+                route.endpoint,
+                escape(&label)
+            )
+        };
+        family(
+            &mut s,
+            "routed_messages_total",
+            "counter",
+            "Messages routed to an endpoint by the bus controller.",
+            self.routes
+                .iter()
+                .map(|route| (route_labels(route), route.messages)),
+        );
+        family(
+            &mut s,
+            "routed_bytes_total",
+            "counter",
+            "Payload bytes routed to an endpoint by the bus controller.",
+            self.routes
+                .iter()
+                .map(|route| (route_labels(route), route.bytes)),
+        );
+
+        for (name, kind, help, value) in [
+            (
+                "unicast_misses_total",
+                "counter",
+                "Unicast messages no endpoint matched.",
+                self.unicast_misses,
+            ),
+            (
+                "buffered_total",
+                "counter",
+                "Messages the bus controller buffered for their ttl.",
+                self.buffered,
+            ),
+            (
+                "expired_total",
+                "counter",
+                "Messages dropped because their deadline or ttl passed.",
+                self.expired,
+            ),
+            (
+                "rejoins_total",
+                "counter",
+                "Rejoins of the bus after being disconnected.",
+                self.rejoins,
+            ),
+            (
+                "decode_failures_total",
+                "counter",
+                "Messages a receiver failed to decode.",
+                self.decode_failures,
+            ),
+            (
+                "type_uuid_not_found_total",
+                "counter",
+                "Messages skipped because their type is not in the message box.",
+                self.type_uuid_not_found,
+            ),
+            (
+                "unacked_dropped_total",
+                "counter",
+                "Reliable messages forgotten by the bus controller before they were acked.",
+                self.unacked_dropped,
+            ),
+            (
+                "memory_regions",
+                "gauge",
+                "Memory regions alive in the process.",
+                self.memory_regions as u64,
+            ),
+        ] {
+            family(&mut s, name, kind, help, [(String::new(), value)]);
+        }
+
+        s
+    }
+}
+
+#[cfg(feature = "prometheus")]
+fn family(
+    s: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    samples: impl IntoIterator<Item = (String, u64)>,
+) {
+    use std::fmt::Write;
+
+    let _ = writeln!(s, "# HELP ipmb_{name} {help}");
+    let _ = writeln!(s, "# TYPE ipmb_{name} {kind}");
+    for (labels, value) in samples {
+        if labels.is_empty() {
+            let _ = writeln!(s, "ipmb_{name} {value}");
+        } else {
+            let _ = writeln!(s, "ipmb_{name}{{{labels}}} {value}");
+        }
+    }
+}
+
+#[cfg(feature = "prometheus")]
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::Counters;
+    use crate::{label, EndpointID};
+
+    #[test]
+    fn routes() {
+        let counters = Counters::default();
+        let (earth, mars) = (EndpointID::new(), EndpointID::new());
+
+        counters.route(earth, &label!("earth"), 10);
+        counters.route(mars, &label!("mars"), 1);
+        counters.route(earth, &label!("earth", "blue"), 5);
+        counters.forget(mars);
+
+        let metrics = counters.snapshot();
+        assert_eq!(metrics.routes.len(), 1);
+        assert_eq!(metrics.routes[0].endpoint, earth);
+        assert_eq!(metrics.routes[0].label, label!("earth", "blue"));
+        assert_eq!(
+            (metrics.routes[0].messages, metrics.routes[0].bytes),
+            (2, 15)
+        );
+
+        #[cfg(feature = "prometheus")]
+        assert!(metrics.to_prometheus().contains(&format!(
+            "ipmb_routed_bytes_total{{endpoint=\"{earth}\",label=\"earth,blue\"}} 15\n"
+        )));
+    }
+}
@@ -1,4 +1,6 @@
-use crate::Label;
+use crate::{Label, LabelOp};
+use serde::{Deserialize, Serialize};
+use std::{num::NonZeroUsize, path::PathBuf};
 
 /// Parameters for joining the bus.
 #[derive(Debug, Clone)]
@@ -11,6 +13,32 @@ pub struct Options {
     pub token: String,
     /// Whether the endpoint can become a bus controller.
     pub controller_affinity: bool,
+    /// Receive a [`MembershipEvent`](crate::MembershipEvent) when an endpoint whose label matches joins or leaves the bus.
+    /// Endpoints already on the bus are reported as joined, again after each rejoin.
+    pub membership: Option<LabelOp>,
+    /// The number of messages the bus controller holds for the endpoint when it doesn't read fast enough,
+    /// on top of what the platform buffers.
+    pub queue_capacity: NonZeroUsize,
+    /// What the bus controller does with a message when the queue of the endpoint is full.
+    pub overflow: Overflow,
+    /// Payloads larger than this many bytes are sent in a [`MemoryRegion`](crate::MemoryRegion)
+    /// instead of through the socket, whose messages are limited in size. Receivers get them as usual.
+    ///
+    /// On Linux it's capped at 32 KiB. A send fails with [`SendError::Spill`](crate::SendError::Spill)
+    /// if the memory region can't be created.
+    pub spill_threshold: usize,
+    /// Drop messages that arrive after a later one of the same [`Sequence`](crate::Sequence) stream,
+    /// and return [`RecvError::Gap`](crate::RecvError::Gap) ahead of a message when earlier ones were lost.
+    pub ordered: bool,
+    /// A directory where the bus controller keeps messages buffered for their [`ttl`](crate::Selector::ttl),
+    /// a bus controller elected after it crashed reloads them. Only used if this endpoint becomes the bus controller.
+    ///
+    /// Messages carrying objects or memory regions, including spilled payloads, are only kept in memory.
+    pub spool: Option<PathBuf>,
+    /// Receive a [`Tap`](crate::Tap) for every message the bus controller routes, buffers or drops.
+    /// Taps are copies, monitoring doesn't take messages from their receivers. A monitor is only
+    /// sent messages addressed to it with [`Selector::to_endpoint`](crate::Selector::to_endpoint).
+    pub monitor: bool,
 }
 
 impl Options {
@@ -24,6 +52,28 @@ impl Options {
             label,
             token: token.into(),
             controller_affinity: true,
+            membership: None,
+            queue_capacity: NonZeroUsize::new(64).unwrap(),
+            overflow: Overflow::Block,
+            spill_threshold: 32 << 10,
+            ordered: false,
+            spool: None,
+            monitor: false,
         }
     }
 }
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Overflow {
+    /// Hold up the senders to the endpoint until it reads, the bus controller keeps routing for others.
+    ///
+    /// On Linux the bus controller stops reading from a held sender, whose sends then wait or fail
+    /// with `SendError::Timeout`/`SendError::Full`. Elsewhere the messages of a held sender wait in the
+    /// bus controller. The bus controller never waits for the endpoint in its own process, the
+    /// message is dropped for it as with `DropNewest`.
+    Block,
+    /// Drop the oldest message in the queue, whatever its priority.
+    DropOldest,
+    /// Don't route the message to the endpoint.
+    DropNewest,
+}
@@ -7,9 +7,7 @@ use fd::Local;
 pub use fd::{Fd, Remote};
 pub(crate) use io_mul::IoMultiplexing;
 use std::{
-    ffi, io, mem,
-    os::fd::RawFd,
-    ptr, slice,
+    ffi, io, mem, ptr, slice,
     sync::{mpsc, Arc, Once},
     time::Duration,
 };
@@ -19,6 +17,8 @@ mod fd;
 mod io_mul;
 
 static MAXIMUM_BUF_SIZE: i32 = 64 << 10;
+/// Larger payloads wouldn't fit in a packet of `MAXIMUM_BUF_SIZE` next to the selector.
+pub(crate) const MAX_SPILL_THRESHOLD: usize = 32 << 10;
 
 impl MemoryRegion {
     pub(crate) fn obj_new(size: usize) -> Option<Object> {
@@ -208,10 +208,12 @@ pub(crate) fn look_up(
     }
 }
 
+/// Listen as the bus controller, the endpoint in this process queues at most `capacity` messages for it.
 pub(crate) fn register(
     identifier: &str,
+    capacity: usize,
     im: Arc<IoMultiplexing>,
-) -> Result<(IoHub, mpsc::Sender<EncodedMessage>, EndpointID), Error> {
+) -> Result<(IoHub, mpsc::SyncSender<EncodedMessage>, EndpointID), Error> {
     unsafe {
         let fd = libc::socket(libc::AF_UNIX, libc::SOCK_SEQPACKET | libc::SOCK_CLOEXEC, 0);
         if fd == -1 {
@@ -241,7 +243,7 @@ pub(crate) fn register(
             return Err(Error::IoError(io::Error::last_os_error()));
         }
 
-        let (bus_tx, bus_rx) = mpsc::channel();
+        let (bus_tx, bus_rx) = mpsc::sync_channel(capacity);
         Ok((
             IoHub::for_bus_controller(fd, bus_rx, im),
             bus_tx,
@@ -252,7 +254,12 @@ pub(crate) fn register(
 
 pub(crate) struct IoHub {
     bus_rx: Option<mpsc::Receiver<EncodedMessage>>,
-    local_list: Vec<Local>,
+    /// The endpoint sending on `bus_rx`, and whether it's held.
+    bus_source: (Option<EndpointID>, bool),
+    /// Connections with the endpoint that last sent on them.
+    local_list: Vec<(Local, Option<EndpointID>)>,
+    /// Connections that are not read until released, see `hold`.
+    held_list: Vec<(Local, EndpointID)>,
     listener: Option<Fd>,
     in_buffer: Vec<libc::epoll_event>,
     im: Arc<IoMultiplexing>,
@@ -268,7 +275,9 @@ impl IoHub {
 
         Self {
             bus_rx: Some(bus_rx),
+            bus_source: (None, false),
             local_list: vec![],
+            held_list: vec![],
             listener: Some(listener),
             in_buffer: Vec::with_capacity(2),
             im,
@@ -280,7 +289,9 @@ impl IoHub {
 
         Self {
             bus_rx: None,
-            local_list: vec![local],
+            bus_source: (None, false),
+            local_list: vec![(local, None)],
+            held_list: vec![],
             listener: None,
             in_buffer: Vec::with_capacity(2),
             im,
@@ -295,9 +306,10 @@ impl IoHub {
         let _ = remote;
 
         'ret: loop {
-            if let Some(ref rx) = self.bus_rx {
+            if let (Some(ref rx), (_, false)) = (&self.bus_rx, self.bus_source) {
                 match rx.try_recv() {
                     Ok(message) => {
+                        self.bus_source.0 = message.selector.source;
                         break Ok(message);
                     }
                     Err(mpsc::TryRecvError::Empty) => {}
@@ -333,21 +345,24 @@ impl IoHub {
                                     mem::size_of_val(&MAXIMUM_BUF_SIZE) as _,
                                 );
                                 self.im.register(&local.0);
-                                self.local_list.push(local);
+                                self.local_list.push((local, None));
                             }
                         }
                         continue;
                     }
                 }
-                if let Some((i, local)) = self
+                if let Some((i, (local, source))) = self
                     .local_list
                     .iter_mut()
                     .enumerate()
-                    .find(|(_, p)| ev.u64 == p.0.as_raw() as u64)
+                    .find(|(_, (p, _))| ev.u64 == p.0.as_raw() as u64)
                 {
                     let r = EncodedMessage::from_local(local);
-                    if r.is_err() {
-                        self.local_list.swap_remove(i);
+                    match &r {
+                        Ok(message) => *source = message.selector.source.or(*source),
+                        Err(_) => {
+                            self.local_list.swap_remove(i);
+                        }
                     }
                     break 'ret r;
                 }
@@ -355,15 +370,46 @@ impl IoHub {
         }
     }
 
+    /// Stop reading what `source` sends, or resume with `held` false. Its messages wait in the
+    /// platform buffer, so its sends fail with `Error::Timeout` once that's full.
+    pub fn hold(&mut self, source: EndpointID, held: bool) {
+        if self.bus_source.0 == Some(source) {
+            self.bus_source.1 = held;
+        }
+
+        if held {
+            while let Some(i) = self
+                .local_list
+                .iter()
+                .position(|(_, sender)| *sender == Some(source))
+            {
+                let (local, _) = self.local_list.swap_remove(i);
+                self.im.unregister(&local.0);
+                self.held_list.push((local, source));
+            }
+        } else {
+            while let Some(i) = self
+                .held_list
+                .iter()
+                .position(|(_, sender)| *sender == source)
+            {
+                let (local, _) = self.held_list.swap_remove(i);
+                self.im.register(&local.0);
+                self.local_list.push((local, Some(source)));
+            }
+        }
+    }
+
     pub fn io_multiplexing(&self) -> Arc<IoMultiplexing> {
         self.im.clone()
     }
 }
 
 impl<T: MessageBox> message::Message<T> {
-    fn encode_inner(&self) -> (&'static [u8], Vec<u8>, Vec<u8>) {
+    fn encode_inner(&self) -> (&'static [u8], Vec<u8>) {
         // iov data
         let mut size = 4 // version 
+            + 4 // fd count
             + 4 // selector size
             + 4 // payload size
             ;
@@ -383,7 +429,13 @@ impl<T: MessageBox> message::Message<T> {
                 u32::from_ne_bytes([0xFF, v.major(), v.minor(), v.patch()]),
             );
 
-            let selector_size_ptr = version_ptr.offset(1);
+            let fd_count_ptr = version_ptr.offset(1);
+            ptr::write(
+                fd_count_ptr,
+                (self.objects.len() + self.memory_regions.len()) as _,
+            );
+
+            let selector_size_ptr = fd_count_ptr.offset(1);
             ptr::write(selector_size_ptr, selector_data.len() as _);
 
             let selector_ptr = selector_size_ptr.offset(1) as *mut u8;
@@ -399,42 +451,19 @@ impl<T: MessageBox> message::Message<T> {
             slice::from_raw_parts(payload_ptr, payload_bytes.len())
         };
 
-        // control data
-        let control_len: u32 =
-            ((self.objects.len() + self.memory_regions.len()) * mem::size_of::<RawFd>()) as _;
-        size = unsafe { libc::CMSG_SPACE(control_len) } as _;
-        let mut control_data: Vec<u8> = alloc_buffer::<usize>(size);
-        unsafe {
-            let control_ptr = control_data.as_mut_ptr() as *mut libc::cmsghdr;
-            let control_ref = &mut *control_ptr;
-            control_ref.cmsg_len = libc::CMSG_LEN(control_len) as _;
-            control_ref.cmsg_level = libc::SOL_SOCKET;
-            control_ref.cmsg_type = libc::SCM_RIGHTS;
-
-            let mut control_data_ptr = libc::CMSG_DATA(control_ptr) as *mut RawFd;
-            for object in self
-                .objects
-                .iter()
-                .chain(self.memory_regions.iter().map(|region| region.object()))
-            {
-                ptr::write(control_data_ptr, object.as_raw());
-                control_data_ptr = control_data_ptr.offset(1);
-            }
-        }
-
-        (payload_data, iov_data, control_data)
+        (payload_data, iov_data)
     }
 
     pub(crate) fn into_encoded(self) -> EncodedMessage {
-        let (payload_data, iov_data, control_data) = self.encode_inner();
+        let (payload_data, iov_data) = self.encode_inner();
 
         EncodedMessage {
             selector: self.selector,
             payload_data,
             iov_data,
-            control_data,
             objects: self.objects,
             memory_regions: self.memory_regions,
+            spill: None,
         }
     }
 }
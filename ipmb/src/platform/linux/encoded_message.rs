@@ -2,24 +2,37 @@ use super::fd::{Local, Remote};
 use crate::{
     decode, message, util::Align4, version, Error, MemoryRegion, Object, Selector, Version,
 };
-use std::{io, mem, os::fd::RawFd, ptr, slice};
+use std::{
+    io, mem,
+    os::fd::RawFd,
+    ptr, slice,
+    time::{Duration, Instant},
+};
 use type_uuid::TypeUuid;
 
+/// The kernel passes at most `SCM_MAX_FD` fds in one packet.
+const MAX_FDS_PER_PACKET: usize = 253;
+
 /// Message layout
 /// | version (magic__major__minor__patch)
+/// | fd_count (u32)
 /// | selector_size (u32)
 /// | selector
 /// | selector_padding
 /// | payload_size (u32)
 /// | payload
 /// | payload_padding
+///
+/// Fds beyond the first `MAX_FDS_PER_PACKET` follow in packets of their own, right after the message,
+/// each with the number of its fds (u32) as data.
 pub(crate) struct EncodedMessage {
     pub selector: crate::Selector,
     pub payload_data: &'static [u8],
     pub iov_data: Vec<u8>,
-    pub control_data: Vec<u8>,
     pub objects: Vec<crate::Object>,
     pub memory_regions: Vec<crate::MemoryRegion>,
+    /// Holds the payload of a spilled message, see [`unspill`](EncodedMessage::unspill).
+    pub spill: Option<crate::MemoryRegion>,
 }
 
 impl EncodedMessage {
@@ -37,9 +50,10 @@ impl EncodedMessage {
     pub fn from_local(local: &mut Local) -> Result<Self, Error> {
         unsafe {
             // peek meta
+            let mut header = [0u32; 2];
             let mut iov = libc::iovec {
-                iov_base: ptr::null_mut(),
-                iov_len: 0,
+                iov_base: header.as_mut_ptr() as _,
+                iov_len: mem::size_of_val(&header),
             };
 
             let mut hdr: libc::msghdr = mem::zeroed();
@@ -58,46 +72,29 @@ impl EncodedMessage {
 
             let meta = Meta {
                 iov_len: r as _,
-                control_len: 32, // TODO: How to peek this?
+                fd_count: header[1] as _,
             };
 
             // recv payload
             let mut iov_data: Vec<u8> = super::alloc_buffer::<u32>(meta.iov_len as _);
-            iov.iov_base = iov_data.as_mut_ptr() as _;
-            iov.iov_len = iov_data.len();
-
-            let mut control_data: Vec<u8> = super::alloc_buffer::<usize>(meta.control_len as _);
-            hdr.msg_control = control_data.as_mut_ptr() as _;
-            hdr.msg_controllen = control_data.len() as _;
-
-            hdr.msg_flags = 0;
+            let mut iov = libc::iovec {
+                iov_base: iov_data.as_mut_ptr() as _,
+                iov_len: iov_data.len(),
+            };
+            hdr.msg_iov = &mut iov;
 
-            r = libc::recvmsg(local.0.as_raw(), &mut hdr, 0);
+            let mut objects = vec![];
+            r = recv_fds(
+                local,
+                &mut hdr,
+                meta.fd_count.min(MAX_FDS_PER_PACKET),
+                &mut objects,
+            );
             // When remote disconnected, recvmsg returns 0
-            if r <= 0
-                || (hdr.msg_flags & libc::MSG_TRUNC == libc::MSG_TRUNC)
-                || (hdr.msg_flags & libc::MSG_CTRUNC == libc::MSG_CTRUNC)
-            {
+            if r <= 0 || (r as usize) < mem::size_of_val(&header) {
                 return Err(Error::Disconnect);
             }
 
-            // parse
-            let mut objects = vec![];
-
-            if hdr.msg_controllen > 0 {
-                let control_ptr = control_data.as_ptr() as *const libc::cmsghdr;
-                let mut control_data_ptr = libc::CMSG_DATA(control_ptr) as *const RawFd;
-
-                let control_count = ((*control_ptr).cmsg_len as usize
-                    - (control_data_ptr as usize - control_ptr as usize))
-                    / mem::size_of::<RawFd>();
-
-                for _ in 0..control_count {
-                    objects.push(Object::from_raw(ptr::read(control_data_ptr)));
-                    control_data_ptr = control_data_ptr.offset(1);
-                }
-            }
-
             let version_ptr = iov_data.as_ptr() as *const u32;
             let [magic, major, minor, patch]: [u8; 4] = u32::to_ne_bytes(*version_ptr);
             if magic != 0xFF {
@@ -109,7 +106,24 @@ impl EncodedMessage {
                 return Err(Error::VersionMismatch(remote_version, None));
             }
 
-            let selector_size_ptr = version_ptr.offset(1);
+            // The remaining fds
+            while objects.len() < meta.fd_count {
+                let mut count = 0u32;
+                let mut iov = libc::iovec {
+                    iov_base: &mut count as *mut u32 as _,
+                    iov_len: mem::size_of_val(&count),
+                };
+                hdr.msg_iov = &mut iov;
+
+                let max = (meta.fd_count - objects.len()).min(MAX_FDS_PER_PACKET);
+                let received = objects.len();
+                r = recv_fds(local, &mut hdr, max, &mut objects);
+                if r <= 0 || count as usize != objects.len() - received {
+                    return Err(Error::Disconnect);
+                }
+            }
+
+            let selector_size_ptr = version_ptr.offset(2);
             let selector_size = *selector_size_ptr;
             let selector_ptr = selector_size_ptr.offset(1) as *const u8;
 
@@ -135,28 +149,50 @@ impl EncodedMessage {
                     *payload_size_ptr as _,
                 ),
                 iov_data,
-                control_data,
                 objects,
                 memory_regions,
+                spill: None,
             })
         }
     }
 
     pub fn send(&mut self, remote: &Remote) -> Result<(), Error> {
-        let mut iov = libc::iovec {
-            iov_base: ptr::null_mut(),
-            iov_len: 0,
-        };
-        let mut hdr: libc::msghdr = unsafe { mem::zeroed() };
-        hdr.msg_iov = &mut iov;
-        hdr.msg_iovlen = 1;
+        self.send_inner(remote, 0)
+    }
+
+    /// Returns `Error::Timeout` if the kernel buffer is still full after `timeout`.
+    pub fn send_timeout(&mut self, remote: &Remote, timeout: Duration) -> Result<(), Error> {
+        let end = Instant::now() + timeout;
 
-        // send payload
-        iov.iov_base = self.iov_data.as_mut_ptr() as _;
-        iov.iov_len = self.iov_data.len();
+        loop {
+            match self.send_inner(remote, libc::MSG_DONTWAIT) {
+                Err(Error::Timeout) => {}
+                r => break r,
+            }
 
-        hdr.msg_control = self.control_data.as_mut_ptr() as _;
-        hdr.msg_controllen = self.control_data.len() as _;
+            let remain = end.saturating_duration_since(Instant::now());
+            if remain.is_zero() {
+                break Err(Error::Timeout);
+            }
+
+            let mut pfd = libc::pollfd {
+                fd: remote.lock().as_raw(),
+                events: libc::POLLOUT,
+                revents: 0,
+            };
+            let remain_ms = ((remain.as_nanos() + 999_999) / 1_000_000).min(i32::MAX as _);
+            unsafe { libc::poll(&mut pfd, 1, remain_ms as _) };
+        }
+    }
+
+    fn send_inner(&mut self, remote: &Remote, flags: i32) -> Result<(), Error> {
+        let fds: Vec<RawFd> = self
+            .objects
+            .iter()
+            .chain(self.memory_regions.iter().map(|region| region.object()))
+            .map(|object| object.as_raw())
+            .collect();
+        let mut chunks = fds.chunks(MAX_FDS_PER_PACKET);
 
         // Add memory region's ref count
         for r in self.memory_regions.iter() {
@@ -164,12 +200,29 @@ impl EncodedMessage {
         }
 
         let remote_guard = remote.lock();
-        let r = unsafe { libc::sendmsg(remote_guard.as_raw(), &hdr, 0) };
-        if r == -1 {
+        let mut r = send_fds(
+            remote_guard.as_raw(),
+            &mut self.iov_data,
+            chunks.next().unwrap_or_default(),
+            flags,
+        );
+        // The message is on its way, the remaining fds must follow
+        for chunk in chunks {
+            if r.is_err() {
+                break;
+            }
+            let mut count = (chunk.len() as u32).to_ne_bytes();
+            r = send_fds(remote_guard.as_raw(), &mut count, chunk, 0);
+        }
+
+        if let Err(err) = r {
             for r in self.memory_regions.iter() {
                 r.ref_count_inner(-1);
             }
-            log::error!("sendmsg: {}", io::Error::last_os_error());
+            if err.kind() == io::ErrorKind::WouldBlock {
+                return Err(Error::Timeout);
+            }
+            log::error!("sendmsg: {}", err);
             Err(Error::Disconnect)
         } else {
             Ok(())
@@ -177,7 +230,171 @@ impl EncodedMessage {
     }
 }
 
+/// Receive one packet into the iov of `hdr` with up to `max` fds, which are appended to `objects`.
+unsafe fn recv_fds(
+    local: &Local,
+    hdr: &mut libc::msghdr,
+    max: usize,
+    objects: &mut Vec<Object>,
+) -> isize {
+    let mut control_data: Vec<u8> = if max > 0 {
+        super::alloc_buffer::<usize>(libc::CMSG_SPACE((max * mem::size_of::<RawFd>()) as _) as _)
+    } else {
+        vec![]
+    };
+    hdr.msg_control = control_data.as_mut_ptr() as _;
+    hdr.msg_controllen = control_data.len() as _;
+    hdr.msg_flags = 0;
+
+    let r = libc::recvmsg(local.0.as_raw(), hdr, 0);
+    if r <= 0 {
+        return r;
+    }
+
+    let mut control_ptr = libc::CMSG_FIRSTHDR(hdr);
+    while !control_ptr.is_null() {
+        if (*control_ptr).cmsg_level == libc::SOL_SOCKET
+            && (*control_ptr).cmsg_type == libc::SCM_RIGHTS
+        {
+            let mut control_data_ptr = libc::CMSG_DATA(control_ptr) as *const RawFd;
+
+            let control_count = ((*control_ptr).cmsg_len as usize
+                - (control_data_ptr as usize - control_ptr as usize))
+                / mem::size_of::<RawFd>();
+
+            for _ in 0..control_count {
+                objects.push(Object::from_raw(ptr::read_unaligned(control_data_ptr)));
+                control_data_ptr = control_data_ptr.offset(1);
+            }
+        }
+        control_ptr = libc::CMSG_NXTHDR(hdr, control_ptr);
+    }
+
+    // Checked after taking the fds, so they are closed
+    if (hdr.msg_flags & libc::MSG_TRUNC == libc::MSG_TRUNC)
+        || (hdr.msg_flags & libc::MSG_CTRUNC == libc::MSG_CTRUNC)
+    {
+        return -1;
+    }
+
+    r
+}
+
+/// Send `data` as one packet along with `fds`.
+fn send_fds(fd: RawFd, data: &mut [u8], fds: &[RawFd], flags: i32) -> io::Result<()> {
+    let mut iov = libc::iovec {
+        iov_base: data.as_mut_ptr() as _,
+        iov_len: data.len(),
+    };
+    let mut hdr: libc::msghdr = unsafe { mem::zeroed() };
+    hdr.msg_iov = &mut iov;
+    hdr.msg_iovlen = 1;
+
+    let mut control_data: Vec<u8> = vec![];
+    if !fds.is_empty() {
+        let control_len = mem::size_of_val(fds) as u32;
+        control_data = super::alloc_buffer::<usize>(unsafe { libc::CMSG_SPACE(control_len) } as _);
+        unsafe {
+            let control_ptr = control_data.as_mut_ptr() as *mut libc::cmsghdr;
+            let control_ref = &mut *control_ptr;
+            control_ref.cmsg_len = libc::CMSG_LEN(control_len) as _;
+            control_ref.cmsg_level = libc::SOL_SOCKET;
+            control_ref.cmsg_type = libc::SCM_RIGHTS;
+
+            ptr::copy_nonoverlapping(
+                fds.as_ptr(),
+                libc::CMSG_DATA(control_ptr) as *mut RawFd,
+                fds.len(),
+            );
+        }
+
+        hdr.msg_control = control_data.as_mut_ptr() as _;
+        hdr.msg_controllen = control_data.len() as _;
+    }
+
+    let r = unsafe { libc::sendmsg(fd, &hdr, flags) };
+    if r == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
 struct Meta {
     iov_len: u32,
-    control_len: u32,
+    fd_count: usize,
+}
+
+#[cfg(test)]
+mod test {
+    use super::{EncodedMessage, Local, Remote, MAX_FDS_PER_PACKET};
+    use crate::{platform::linux::Fd, LabelOp, MemoryRegion, Message, Object, Selector};
+    use std::mem;
+
+    fn socket_pair() -> (Remote, Local) {
+        let mut pair = [0, 0];
+        let r = unsafe {
+            libc::socketpair(
+                libc::AF_UNIX,
+                libc::SOCK_SEQPACKET | libc::SOCK_CLOEXEC,
+                0,
+                pair.as_mut_ptr(),
+            )
+        };
+        assert_ne!(r, -1);
+
+        unsafe {
+            (
+                Remote::new(Fd::from_raw(pair[0])),
+                Local(Fd::from_raw(pair[1])),
+            )
+        }
+    }
+
+    fn file_size(obj: &Object) -> i64 {
+        let mut stat: libc::stat = unsafe { mem::zeroed() };
+        assert_ne!(unsafe { libc::fstat(obj.as_raw(), &mut stat) }, -1);
+        stat.st_size
+    }
+
+    fn round_trip(objects: usize, memory_regions: usize) -> EncodedMessage {
+        let (remote, mut local) = socket_pair();
+
+        let mut msg = Message::new(Selector::unicast(LabelOp::True), "fds".to_string());
+        // Sizes tell the fds apart
+        for i in 0..objects {
+            msg.objects.push(MemoryRegion::obj_new(i).unwrap());
+        }
+        for i in 0..memory_regions {
+            msg.memory_regions.push(MemoryRegion::new(i + 1).unwrap());
+        }
+        msg.selector.memory_region_count = memory_regions as _;
+
+        msg.into_encoded().send(&remote).unwrap();
+
+        EncodedMessage::from_local(&mut local).unwrap()
+    }
+
+    #[test]
+    fn no_objects() {
+        let encoded_msg = round_trip(0, 0);
+        assert!(encoded_msg.objects.is_empty());
+        assert!(encoded_msg.memory_regions.is_empty());
+        assert_eq!(encoded_msg.payload_data.len(), 4);
+    }
+
+    #[test]
+    fn many_objects() {
+        let encoded_msg = round_trip(MAX_FDS_PER_PACKET * 2 + 10, 100);
+
+        assert_eq!(encoded_msg.objects.len(), MAX_FDS_PER_PACKET * 2 + 10);
+        for (i, obj) in encoded_msg.objects.iter().enumerate() {
+            assert_eq!(file_size(obj), i as i64);
+        }
+
+        assert_eq!(encoded_msg.memory_regions.len(), 100);
+        for (i, region) in encoded_msg.memory_regions.iter().enumerate() {
+            assert_eq!(region.buffer_size(), i as u64 + 1);
+        }
+    }
 }
@@ -27,6 +27,12 @@ impl Fd {
     }
 }
 
+impl AsRawFd for Fd {
+    fn as_raw_fd(&self) -> fd::RawFd {
+        self.as_raw()
+    }
+}
+
 pub struct Remote {
     v: i32,
     fd: Mutex<Fd>,
@@ -45,23 +51,36 @@ impl Remote {
     }
 
     pub fn is_dead(&self) -> bool {
-        let mut err: i32 = 0;
-        let mut len: u32 = mem::size_of_val(&err) as _;
+        let mut pfd = libc::pollfd {
+            fd: self.v,
+            events: 0,
+            revents: 0,
+        };
+        // SO_ERROR stays clear when the peer closes, but both directions are shut down then
+        let r = unsafe { libc::poll(&mut pfd, 1, 0) };
+
+        r == -1 || pfd.revents & (libc::POLLHUP | libc::POLLERR | libc::POLLNVAL) != 0
+    }
+
+    /// The pid and uid of the process that created the socket pair, i.e. the endpoint.
+    pub fn peer_credentials(&self) -> Option<(u32, u32)> {
+        let mut cred = libc::ucred {
+            pid: 0,
+            uid: 0,
+            gid: 0,
+        };
+        let mut len = mem::size_of_val(&cred) as libc::socklen_t;
         let r = unsafe {
             libc::getsockopt(
                 self.v,
                 libc::SOL_SOCKET,
-                libc::SO_ERROR,
-                &mut err as *mut _ as *mut _,
+                libc::SO_PEERCRED,
+                &mut cred as *mut _ as _,
                 &mut len,
             )
         };
 
-        if r == -1 || err != 0 {
-            true
-        } else {
-            false
-        }
+        (r == 0).then_some((cred.pid as u32, cred.uid))
     }
 }
 
@@ -1,8 +1,8 @@
 use super::Fd;
-use std::{ffi, mem, time::Duration};
+use std::{ffi, mem, ptr, time::Duration};
 
 pub struct IoMultiplexing {
-    fd: Fd,
+    pub(crate) fd: Fd,
     pub(crate) waker_fd: Fd,
 }
 
@@ -38,6 +38,18 @@ impl IoMultiplexing {
         }
     }
 
+    pub(crate) fn unregister(&self, fd: &Fd) {
+        unsafe {
+            let r = libc::epoll_ctl(
+                self.fd.as_raw(),
+                libc::EPOLL_CTL_DEL,
+                fd.as_raw(),
+                ptr::null_mut(),
+            );
+            assert_ne!(r, -1);
+        }
+    }
+
     pub(crate) fn wait(&self, events: &mut Vec<libc::epoll_event>, timeout: Option<Duration>) {
         unsafe {
             let n = libc::epoll_wait(
@@ -68,6 +80,20 @@ impl IoMultiplexing {
         }
     }
 
+    /// Reset the waker only if it was woken, never blocks.
+    #[cfg(feature = "async")]
+    pub(crate) fn try_clear_waker(&self) {
+        let mut pfd = libc::pollfd {
+            fd: self.waker_fd.as_raw(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let r = unsafe { libc::poll(&mut pfd, 1, 0) };
+        if r == 1 && (pfd.revents & libc::POLLIN) != 0 {
+            self.clear_waker();
+        }
+    }
+
     pub(crate) fn clear_waker(&self) {
         unsafe {
             let mut u: u64 = 0;
@@ -13437,6 +13437,7 @@ pub const MACH_SEND_MSG: i32 = 1;
 pub const MACH_SEND_MSG_TOO_SMALL: kern_return_t = 0x10000008;
 pub const MACH_SEND_NO_BUFFER: kern_return_t = 0x1000000d;
 pub const MACH_SEND_TIMED_OUT: kern_return_t = 0x10000004;
+pub const MACH_SEND_TIMEOUT: i32 = 0x10;
 pub const MACH_SEND_TOO_LARGE: kern_return_t = 0x1000000e;
 pub const TASK_BOOTSTRAP_PORT: i32 = 4;
 pub const VM_INHERIT_SHARE: vm_inherit_t = 0;
@@ -14,7 +14,7 @@ use std::{
     ptr, slice,
     sync::{
         mpsc,
-        mpsc::{Receiver, Sender, TryRecvError},
+        mpsc::{Receiver, SyncSender, TryRecvError},
         Arc, Once,
     },
     thread,
@@ -31,6 +31,8 @@ mod memory_region;
 // 	        << ((right) + ((mach_port_right_t) 16))))
 // #define MACH_PORT_TYPE_DEAD_NAME    MACH_PORT_TYPE(MACH_PORT_RIGHT_DEAD_NAME)
 const MACH_PORT_TYPE_DEAD_NAME: mach_sys::mach_port_type_t = 1 << (4 + 16);
+/// Mach messages aren't limited to a socket buffer.
+pub(crate) const MAX_SPILL_THRESHOLD: usize = usize::MAX;
 
 #[derive(Debug, PartialEq)]
 pub struct Remote {
@@ -48,6 +50,11 @@ impl Remote {
 
         ty & MACH_PORT_TYPE_DEAD_NAME != 0
     }
+
+    /// Not reported for Mach ports.
+    pub fn peer_credentials(&self) -> Option<(u32, u32)> {
+        None
+    }
 }
 
 #[inline]
@@ -109,10 +116,12 @@ pub(crate) fn look_up(
     }
 }
 
+/// Listen as the bus controller, the endpoint in this process queues at most `capacity` messages for it.
 pub(crate) fn register(
     identifier: &str,
+    capacity: usize,
     im: Arc<IoMultiplexing>,
-) -> Result<(IoHub, Sender<EncodedMessage>, EndpointID), Error> {
+) -> Result<(IoHub, SyncSender<EncodedMessage>, EndpointID), Error> {
     let identifier = CString::new(identifier).unwrap();
     let local = MachPort::with_receive_right();
     unsafe {
@@ -124,7 +133,7 @@ pub(crate) fn register(
 
         match r {
             mach_sys::BOOTSTRAP_SUCCESS => {
-                let (bus_sender, bus_receiver) = mpsc::channel();
+                let (bus_sender, bus_receiver) = mpsc::sync_channel(capacity);
                 Ok((
                     IoHub::for_bus_controller(local, bus_receiver, im),
                     bus_sender,
@@ -241,6 +250,12 @@ impl IoHub {
         }
     }
 
+    /// Endpoints share the port of the bus controller, it can't stop reading one of them. Their
+    /// messages wait in the bus controller instead.
+    pub fn hold(&mut self, source: EndpointID, held: bool) {
+        let _ = (source, held);
+    }
+
     pub fn io_multiplexing(&self) -> Arc<IoMultiplexing> {
         self.im.clone()
     }
@@ -362,6 +377,7 @@ impl<T: MessageBox> Message<T> {
             mach_msg,
             objects: self.objects,
             memory_regions: self.memory_regions,
+            spill: None,
         }
     }
 }
@@ -372,6 +388,8 @@ pub(crate) struct EncodedMessage {
     mach_msg: Vec<u8>,
     pub objects: Vec<MachPort>,
     pub memory_regions: Vec<MemoryRegion>,
+    /// Holds the payload of a spilled message, see [`unspill`](EncodedMessage::unspill).
+    pub spill: Option<MemoryRegion>,
 }
 
 impl EncodedMessage {
@@ -383,6 +401,17 @@ impl EncodedMessage {
     }
 
     pub fn send(&mut self, remote: &Remote) -> Result<(), Error> {
+        self.send_inner(remote, None)
+    }
+
+    /// Returns `Error::Timeout` if the port queue is still full after `timeout`.
+    pub fn send_timeout(&mut self, remote: &Remote, timeout: Duration) -> Result<(), Error> {
+        self.send_inner(remote, Some(timeout))
+    }
+
+    fn send_inner(&mut self, remote: &Remote, timeout: Option<Duration>) -> Result<(), Error> {
+        let end = timeout.map(|timeout| Instant::now() + timeout);
+
         unsafe {
             let header_ptr = self.mach_msg.as_mut_ptr() as *mut BaseMessage;
 
@@ -394,13 +423,36 @@ impl EncodedMessage {
             }
 
             loop {
-                let r = mach_sys::mach_msg_send(header_ptr as *mut _);
+                let r = match end {
+                    None => mach_sys::mach_msg_send(header_ptr as *mut _),
+                    Some(end) => mach_sys::mach_msg(
+                        header_ptr as *mut _,
+                        mach_sys::MACH_SEND_MSG | mach_sys::MACH_SEND_TIMEOUT,
+                        (*header_ptr).header.msgh_size,
+                        0,
+                        mach_sys::MACH_PORT_NULL,
+                        end.saturating_duration_since(Instant::now()).as_millis() as _,
+                        mach_sys::MACH_PORT_NULL,
+                    ),
+                };
                 // TODO: handle too large
                 if r == mach_sys::MACH_MSG_SUCCESS {
                     break Ok(());
-                } else if r == mach_sys::MACH_SEND_NO_BUFFER {
-                    // Retry
-                    thread::sleep(Duration::from_millis(200));
+                } else if r == mach_sys::MACH_SEND_NO_BUFFER
+                    && end.map(|end| end > Instant::now()).unwrap_or(true)
+                {
+                    // Retry, but not past the deadline
+                    let retry = Duration::from_millis(200);
+                    thread::sleep(match end {
+                        Some(end) => retry.min(end.saturating_duration_since(Instant::now())),
+                        None => retry,
+                    });
+                } else if r == mach_sys::MACH_SEND_TIMED_OUT || r == mach_sys::MACH_SEND_NO_BUFFER {
+                    for r in self.memory_regions.iter() {
+                        r.ref_count_inner(-1);
+                    }
+
+                    break Err(Error::Timeout);
                 } else {
                     for r in self.memory_regions.iter() {
                         r.ref_count_inner(-1);
@@ -495,6 +547,7 @@ impl EncodedMessage {
                 mach_msg,
                 objects,
                 memory_regions,
+                spill: None,
             })
         }
     }
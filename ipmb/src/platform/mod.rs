@@ -1,4 +1,4 @@
-use crate::{util, Error};
+use crate::{metrics::COUNTERS, util, Error};
 use std::{
     io, mem,
     ops::RangeBounds,
@@ -16,14 +16,17 @@ pub type Object = self::linux::Fd;
 #[cfg(target_os = "linux")]
 pub(crate) use self::linux::{
     look_up, page_mask, register, EncodedMessage, IoHub, IoMultiplexing, Remote,
+    MAX_SPILL_THRESHOLD,
 };
 #[cfg(target_os = "macos")]
 pub(crate) use self::macos::{
     look_up, page_mask, register, EncodedMessage, IoHub, IoMultiplexing, Remote,
+    MAX_SPILL_THRESHOLD,
 };
 #[cfg(target_os = "windows")]
 pub(crate) use self::windows::{
     look_up, page_mask, register, EncodedMessage, IoHub, IoMultiplexing, Remote,
+    MAX_SPILL_THRESHOLD,
 };
 
 #[cfg(target_os = "linux")]
@@ -33,6 +36,27 @@ pub mod macos;
 #[cfg(target_os = "windows")]
 pub mod windows;
 
+impl EncodedMessage {
+    /// Map the payload which the sender spilled into the last memory region of the message, see
+    /// [`Options::spill_threshold`](crate::Options::spill_threshold).
+    pub(crate) fn unspill(&mut self) -> Result<(), Error> {
+        if !self.selector.spilled || self.spill.is_some() {
+            return Ok(());
+        }
+
+        let mut region = self
+            .memory_regions
+            .pop()
+            .ok_or(Error::MemoryRegionMapping)?;
+        let data = region.map(..)?;
+        // The mapping lives as long as `spill`
+        self.payload_data = unsafe { slice::from_raw_parts(data.as_ptr(), data.len()) };
+        self.spill = Some(region);
+
+        Ok(())
+    }
+}
+
 pub struct MemoryRegion {
     header: MappedRegion,
     buffer_size: u64,
@@ -43,6 +67,7 @@ pub struct MemoryRegion {
 impl Drop for MemoryRegion {
     fn drop(&mut self) {
         self.ref_count_inner(-1);
+        COUNTERS.memory_regions.fetch_sub(1, Ordering::Relaxed);
     }
 }
 
@@ -70,6 +95,7 @@ impl MemoryRegion {
 
             let rc: &AtomicU32 = mem::transmute(header.as_slice().as_ptr());
             rc.store(1, Ordering::SeqCst);
+            COUNTERS.memory_regions.fetch_add(1, Ordering::Relaxed);
 
             header
                 .as_mut()
@@ -107,6 +133,7 @@ impl MemoryRegion {
             }
         };
         mr.ref_count_inner(1);
+        COUNTERS.memory_regions.fetch_add(1, Ordering::Relaxed);
 
         mr
     }
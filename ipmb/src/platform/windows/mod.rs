@@ -17,7 +17,7 @@ use std::{
     ptr, slice,
     sync::{
         mpsc,
-        mpsc::{Receiver, Sender, TryRecvError},
+        mpsc::{Receiver, SyncSender, TryRecvError},
         Arc,
     },
     time::Duration,
@@ -37,6 +37,9 @@ mod pipe;
 mod security;
 pub(crate) mod util;
 
+/// Pipe messages aren't limited to a socket buffer.
+pub(crate) const MAX_SPILL_THRESHOLD: usize = usize::MAX;
+
 #[derive(Debug, PartialEq)]
 pub struct Remote {
     pipe: Handle,
@@ -53,6 +56,11 @@ impl Remote {
                 .as_bool()
         }
     }
+
+    /// Not reported for named pipes.
+    pub fn peer_credentials(&self) -> Option<(u32, u32)> {
+        None
+    }
 }
 
 pub(crate) fn look_up(
@@ -144,10 +152,12 @@ pub(crate) fn look_up(
     }
 }
 
+/// Listen as the bus controller, the endpoint in this process queues at most `capacity` messages for it.
 pub(crate) fn register(
     identifier: &str,
+    capacity: usize,
     im: Arc<IoMultiplexing>,
-) -> Result<(IoHub, Sender<EncodedMessage>, EndpointID), Error> {
+) -> Result<(IoHub, SyncSender<EncodedMessage>, EndpointID), Error> {
     unsafe {
         let identifier: HSTRING = format!("\\\\.\\pipe\\{}", identifier).into();
 
@@ -172,7 +182,7 @@ pub(crate) fn register(
             })?;
 
         let local = NamedPipe::new(Handle(pipe_handle), NamedPipeStatus::Free);
-        let (bus_sender, bus_receiver) = mpsc::channel();
+        let (bus_sender, bus_receiver) = mpsc::sync_channel(capacity);
 
         Ok((
             IoHub::for_bus_controller(im, identifier, bus_receiver, local),
@@ -381,6 +391,12 @@ impl IoHub {
         }
     }
 
+    /// Reads on the pipes stay pending, so a sender can't be held here. Its messages wait in the
+    /// bus controller instead.
+    pub fn hold(&mut self, source: EndpointID, held: bool) {
+        let _ = (source, held);
+    }
+
     pub fn io_multiplexing(&self) -> Arc<IoMultiplexing> {
         self.im.clone()
     }
@@ -466,6 +482,8 @@ pub(crate) struct EncodedMessage {
     msg_size: usize,
     pub objects: Vec<Handle>,
     pub memory_regions: Vec<MemoryRegion>,
+    /// Holds the payload of a spilled message, see [`unspill`](EncodedMessage::unspill).
+    pub spill: Option<MemoryRegion>,
 }
 
 impl EncodedMessage {
@@ -490,6 +508,12 @@ impl EncodedMessage {
         )
     }
 
+    /// Writes to a pipe can't time out, this blocks like [`send`](EncodedMessage::send).
+    pub fn send_timeout(&mut self, remote: &Remote, timeout: Duration) -> Result<(), Error> {
+        let _ = timeout;
+        self.send(remote)
+    }
+
     // TODO: Check size
     fn new(mut pipe_msg: Vec<u8>, msg_size: usize) -> Result<Self, Error> {
         unsafe {
@@ -550,6 +574,7 @@ impl EncodedMessage {
                 msg_size,
                 objects,
                 memory_regions,
+                spill: None,
             })
         }
     }
@@ -616,6 +641,7 @@ impl<T: MessageBox> Message<T> {
             msg_size,
             objects: self.objects,
             memory_regions: self.memory_regions,
+            spill: None,
         }
     }
 }
@@ -0,0 +1,176 @@
+use crate::platform::EncodedMessage;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// How soon a message is handled relative to others waiting in the bus controller and the receiver.
+#[derive(
+    Debug, Copy, Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize,
+)]
+pub enum Priority {
+    /// Bulk traffic, e.g. telemetry.
+    Low,
+    #[default]
+    Normal,
+    /// Latency sensitive messages, e.g. input events.
+    High,
+}
+
+const LEVELS: usize = 3;
+/// A waiting level is served at the latest after being passed over this many times.
+const MAX_SKIPS: usize = 8;
+
+/// Messages taken by priority, lower priorities are served now and then so they can't starve.
+#[derive(Default)]
+pub(crate) struct PriorityQueue {
+    /// Messages with the number they were pushed as, to find the oldest across levels.
+    levels: [VecDeque<(u64, EncodedMessage)>; LEVELS],
+    /// How many times each level was passed over while it had messages.
+    skips: [usize; LEVELS],
+    pushed: u64,
+}
+
+impl PriorityQueue {
+    pub fn push_back(&mut self, msg: EncodedMessage) {
+        self.pushed += 1;
+        self.levels[msg.selector.priority as usize].push_back((self.pushed, msg));
+    }
+
+    pub fn len(&self) -> usize {
+        self.levels.iter().map(VecDeque::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.levels.iter().all(VecDeque::is_empty)
+    }
+
+    /// The message `pop_front` takes.
+    pub fn front_mut(&mut self) -> Option<&mut EncodedMessage> {
+        let level = self.next_level()?;
+        self.levels[level].front_mut().map(|(_, msg)| msg)
+    }
+
+    pub fn pop_front(&mut self) -> Option<EncodedMessage> {
+        let level = self.next_level()?;
+
+        for (i, messages) in self.levels.iter().enumerate() {
+            if i == level || messages.is_empty() {
+                self.skips[i] = 0;
+            } else if i < level {
+                self.skips[i] += 1;
+            }
+        }
+
+        self.levels[level].pop_front().map(|(_, msg)| msg)
+    }
+
+    /// Take the message pushed first regardless of its priority, to make room.
+    pub fn pop_oldest(&mut self) -> Option<EncodedMessage> {
+        self.levels
+            .iter_mut()
+            .filter_map(|messages| Some((messages.front()?.0, messages)))
+            .min_by_key(|(pushed, _)| *pushed)?
+            .1
+            .pop_front()
+            .map(|(_, msg)| msg)
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Move the messages for which `f` returns true to a new queue.
+    pub fn extract(&mut self, mut f: impl FnMut(&EncodedMessage) -> bool) -> Self {
+        let mut extracted = Self::default();
+        for (messages, to) in self.levels.iter_mut().zip(extracted.levels.iter_mut()) {
+            let (matched, rest) = messages.drain(..).partition(|(_, msg)| f(msg));
+            *to = matched;
+            *messages = rest;
+        }
+        extracted.pushed = self.pushed;
+        extracted
+    }
+
+    /// Move the messages of `other` behind those of the same priority, they count as pushed now.
+    pub fn append(&mut self, other: Self) {
+        let mut pushed = self.pushed;
+        for (messages, other) in self.levels.iter_mut().zip(other.levels) {
+            for (_, msg) in other {
+                pushed += 1;
+                messages.push_back((pushed, msg));
+            }
+        }
+        self.pushed = pushed;
+    }
+
+    /// The highest level with messages, unless a lower one was passed over too often.
+    fn next_level(&self) -> Option<usize> {
+        let mut levels = (0..LEVELS).rev().filter(|&i| !self.levels[i].is_empty());
+        let highest = levels.next()?;
+
+        Some(
+            levels
+                .find(|&i| self.skips[i] >= MAX_SKIPS)
+                .unwrap_or(highest),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Priority, PriorityQueue, MAX_SKIPS};
+    use crate::{LabelOp, Message, Selector};
+
+    fn push(queue: &mut PriorityQueue, priority: Priority, n: usize) {
+        let mut selector = Selector::unicast(LabelOp::True);
+        selector.priority = priority;
+        queue.push_back(Message::new(selector, n.to_string()).into_encoded());
+    }
+
+    fn pop(queue: &mut PriorityQueue) -> Option<Priority> {
+        queue.pop_front().map(|msg| msg.selector.priority)
+    }
+
+    #[test]
+    fn priority_order() {
+        let mut queue = PriorityQueue::default();
+        push(&mut queue, Priority::Low, 0);
+        push(&mut queue, Priority::Normal, 1);
+        push(&mut queue, Priority::High, 2);
+
+        assert_eq!(queue.len(), 3);
+        assert_eq!(pop(&mut queue), Some(Priority::High));
+        assert_eq!(pop(&mut queue), Some(Priority::Normal));
+        assert_eq!(pop(&mut queue), Some(Priority::Low));
+        assert_eq!(pop(&mut queue), None);
+    }
+
+    #[test]
+    fn no_starvation() {
+        let mut queue = PriorityQueue::default();
+        push(&mut queue, Priority::Low, 0);
+        for n in 0..MAX_SKIPS * 2 {
+            push(&mut queue, Priority::High, n);
+        }
+
+        for _ in 0..MAX_SKIPS {
+            assert_eq!(pop(&mut queue), Some(Priority::High));
+        }
+        assert_eq!(pop(&mut queue), Some(Priority::Low));
+        assert_eq!(pop(&mut queue), Some(Priority::High));
+    }
+
+    #[test]
+    fn pop_oldest() {
+        let mut queue = PriorityQueue::default();
+        push(&mut queue, Priority::High, 0);
+        push(&mut queue, Priority::Low, 1);
+        push(&mut queue, Priority::Normal, 2);
+
+        let oldest =
+            |queue: &mut PriorityQueue| queue.pop_oldest().map(|msg| msg.selector.priority);
+        assert_eq!(oldest(&mut queue), Some(Priority::High));
+        assert_eq!(oldest(&mut queue), Some(Priority::Low));
+        assert_eq!(oldest(&mut queue), Some(Priority::Normal));
+        assert_eq!(oldest(&mut queue), None);
+    }
+}
@@ -0,0 +1,131 @@
+use crate::{Correlation, EndpointID, Selector};
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp,
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+};
+
+/// Numbers a message among those its sender sent to the same destination, see
+/// [`Message::sequence`](crate::Message::sequence).
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct Sequence {
+    /// Identifies the sender, unlike [`Message::source`](crate::Message::source) it stays the same
+    /// when the sender rejoins the bus.
+    pub sender: EndpointID,
+    /// Identifies the destination, derived from the label op, mode, target and priority of the selector.
+    pub stream: u64,
+    /// Counts from 0 in send order.
+    pub number: u64,
+}
+
+/// Hands out the sequence numbers of an endpoint.
+pub(crate) struct Sequencer {
+    sender: EndpointID,
+    next: HashMap<u64, u64>,
+}
+
+impl Sequencer {
+    pub fn new() -> Self {
+        Self {
+            sender: EndpointID::new(),
+            next: HashMap::new(),
+        }
+    }
+
+    /// Number the message of `selector` without using up the number, see [`advance`](Sequencer::advance).
+    ///
+    /// Replies are not numbered, they don't belong to a stream.
+    pub fn stamp(&self, selector: &mut Selector) {
+        if let Some(Correlation::Reply(_) | Correlation::ConfirmReply { .. }) = selector.correlation
+        {
+            return;
+        }
+
+        let stream = Self::stream(selector);
+        selector.sequence = Some(Sequence {
+            sender: self.sender,
+            stream,
+            number: self.next.get(&stream).copied().unwrap_or(0),
+        });
+    }
+
+    /// The message numbered `sequence` was sent, the next one of its stream gets the following number.
+    pub fn advance(&mut self, sequence: Option<Sequence>) {
+        if let Some(sequence) = sequence {
+            self.next.insert(sequence.stream, sequence.number + 1);
+        }
+    }
+
+    fn stream(selector: &Selector) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        selector.label_op.to_string().hash(&mut hasher);
+        (selector.mode as u8).hash(&mut hasher);
+        selector.target.hash(&mut hasher);
+        selector.priority.hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+/// What a receiver makes of the sequence of a message.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Order {
+    /// The first message of the stream or the one following the last.
+    Next,
+    /// Messages `from..to` of the stream are missing.
+    Gap { from: u64, to: u64 },
+    /// The message is a duplicate or arrived after a later one.
+    Late,
+}
+
+/// The next number expected of each stream.
+#[derive(Default)]
+pub(crate) struct Expected(HashMap<(EndpointID, u64), u64>);
+
+impl Expected {
+    pub fn check(&mut self, sequence: Sequence) -> Order {
+        let next = self
+            .0
+            .entry((sequence.sender, sequence.stream))
+            .or_insert(sequence.number);
+
+        let order = match sequence.number.cmp(next) {
+            cmp::Ordering::Less => return Order::Late,
+            cmp::Ordering::Equal => Order::Next,
+            cmp::Ordering::Greater => Order::Gap {
+                from: *next,
+                to: sequence.number,
+            },
+        };
+        *next = sequence.number + 1;
+        order
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Expected, Order, Sequence};
+    use crate::EndpointID;
+
+    #[test]
+    fn gap_detection() {
+        let sender = EndpointID::new();
+        let sequence = |stream, number| Sequence {
+            sender,
+            stream,
+            number,
+        };
+        let mut expected = Expected::default();
+
+        assert_eq!(expected.check(sequence(0, 3)), Order::Next);
+        assert_eq!(expected.check(sequence(0, 4)), Order::Next);
+        assert_eq!(expected.check(sequence(0, 4)), Order::Late);
+        assert_eq!(
+            expected.check(sequence(0, 7)),
+            Order::Gap { from: 5, to: 7 }
+        );
+        assert_eq!(expected.check(sequence(0, 6)), Order::Late);
+        assert_eq!(expected.check(sequence(0, 8)), Order::Next);
+        assert_eq!(expected.check(sequence(1, 0)), Order::Next);
+    }
+}
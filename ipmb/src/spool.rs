@@ -0,0 +1,440 @@
+use crate::{
+    decode, encode, message::EncodedPayload, platform::EncodedMessage, Correlation, EndpointID,
+    Error, LabelOp, Message, Priority, Selector, SelectorMode, Sequence, TraceContext,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+use type_uuid::Bytes;
+
+/// The start of a log, followed by the format version as u32.
+const MAGIC: &[u8; 8] = b"ipmbspl\0";
+/// Bumped whenever `Record` changes, logs of other versions are set aside.
+const FORMAT: u32 = 1;
+/// The log is compacted once it has this many records, and four times as many as live messages.
+const COMPACT_RECORDS: usize = 1024;
+/// Larger records are not written, a larger length read is taken as the end of a corrupt log.
+const MAX_RECORD_SIZE: usize = 16 << 20;
+
+/// Messages buffered by the bus controller, appended to a log file so a bus controller elected
+/// after this one crashed can reload them, see `Options::spool`.
+pub(crate) struct Spool {
+    path: PathBuf,
+    file: File,
+    /// Records in the log.
+    records: usize,
+    /// Messages added and not removed.
+    live: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+enum Record {
+    Add(Box<Entry>),
+    /// The first message added with this sequence left the buffer.
+    Remove(Sequence),
+}
+
+/// A buffered message, kept apart from `Selector` so the wire format can change without
+/// breaking logs. Messages with a target, objects or memory regions are never spooled.
+#[derive(Clone, Serialize, Deserialize)]
+struct Entry {
+    expire: SystemTime,
+    /// The textual form.
+    label_op: String,
+    mode: SelectorMode,
+    uuid: Bytes,
+    ttl: Duration,
+    priority: Priority,
+    deadline: Option<SystemTime>,
+    reliable: bool,
+    source: Option<EndpointID>,
+    /// The id of a request.
+    request: Option<u64>,
+    codec: u8,
+    sequence: Sequence,
+    redelivered: u32,
+    /// The `traceparent` header and the baggage.
+    trace: Option<(String, Vec<(String, String)>)>,
+    #[serde(with = "serde_bytes")]
+    payload: Vec<u8>,
+}
+
+impl Entry {
+    fn new(expire: SystemTime, encoded_msg: &EncodedMessage, sequence: Sequence) -> Self {
+        let selector = &encoded_msg.selector;
+        Self {
+            expire,
+            label_op: selector.label_op.to_string(),
+            mode: selector.mode,
+            uuid: selector.uuid,
+            ttl: selector.ttl,
+            priority: selector.priority,
+            deadline: selector.deadline,
+            reliable: selector.reliable,
+            source: selector.source,
+            request: match selector.correlation {
+                Some(Correlation::Request(id)) => Some(id),
+                _ => None,
+            },
+            codec: selector.codec,
+            sequence,
+            redelivered: selector.redelivered,
+            trace: selector
+                .trace
+                .as_ref()
+                .map(|trace| (trace.to_string(), trace.baggage.clone())),
+            payload: encoded_msg.payload_data.to_vec(),
+        }
+    }
+
+    fn into_encoded(self) -> Option<EncodedMessage> {
+        let label_op = match LabelOp::parse(&self.label_op) {
+            Ok(label_op) => label_op,
+            Err(err) => {
+                log::warn!("spool: {}: {}", self.label_op, err);
+                return None;
+            }
+        };
+
+        let mut selector = Selector::new(label_op, self.mode);
+        selector.uuid = self.uuid;
+        selector.ttl = self.ttl;
+        selector.priority = self.priority;
+        selector.deadline = self.deadline;
+        selector.reliable = self.reliable;
+        selector.source = self.source;
+        selector.correlation = self.request.map(Correlation::Request);
+        selector.codec = self.codec;
+        selector.sequence = Some(self.sequence);
+        selector.redelivered = self.redelivered;
+        selector.trace = self.trace.and_then(|(traceparent, baggage)| {
+            let mut trace = TraceContext::from_traceparent(&traceparent)?;
+            trace.baggage = baggage;
+            Some(trace)
+        });
+
+        let msg = Message {
+            selector,
+            payload: EncodedPayload::new(self.payload),
+            objects: vec![],
+            memory_regions: vec![],
+        };
+        Some(msg.into_encoded())
+    }
+}
+
+impl Spool {
+    /// Open the log of bus `identifier` in `dir`, returns it with the messages that haven't expired.
+    ///
+    /// The log is rewritten with those messages only, records after a torn write or a corrupt length are dropped.
+    /// A log of another format is renamed with its version as extension and a new one started.
+    pub fn open(
+        dir: &Path,
+        identifier: &str,
+    ) -> io::Result<(Self, Vec<(Instant, EncodedMessage)>)> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{identifier}.spool"));
+
+        let (file, live) = compact(&path)?;
+        let now = SystemTime::now();
+        let instant_now = Instant::now();
+        let spool = Self {
+            path,
+            file,
+            records: live.len(),
+            live: live.len(),
+        };
+        let messages = live
+            .into_iter()
+            .filter_map(|entry| {
+                let expire = instant_now + entry.expire.duration_since(now).unwrap_or_default();
+                Some((expire, entry.into_encoded()?))
+            })
+            .collect();
+
+        Ok((spool, messages))
+    }
+
+    /// The sequence a message is kept by in the log, `None` if it can't be kept.
+    ///
+    /// Objects and memory regions don't outlive the process, messages are removed by sequence.
+    pub fn key(encoded_msg: &EncodedMessage) -> Option<Sequence> {
+        if encoded_msg.objects.is_empty()
+            && encoded_msg.memory_regions.is_empty()
+            && encoded_msg.selector.target.is_none()
+        {
+            encoded_msg.selector.sequence
+        } else {
+            None
+        }
+    }
+
+    pub fn add(&mut self, expire: Instant, encoded_msg: &EncodedMessage) {
+        let Some(sequence) = Self::key(encoded_msg) else {
+            return;
+        };
+
+        let expire = SystemTime::now() + expire.saturating_duration_since(Instant::now());
+        self.append(&Record::Add(Box::new(Entry::new(
+            expire,
+            encoded_msg,
+            sequence,
+        ))));
+        self.live += 1;
+    }
+
+    /// The message of `key` left the buffer.
+    pub fn remove(&mut self, key: Option<Sequence>) {
+        if let Some(sequence) = key {
+            self.append(&Record::Remove(sequence));
+            // Expired messages are already gone after a compaction
+            self.live = self.live.saturating_sub(1);
+            self.maybe_compact();
+        }
+    }
+
+    fn append(&mut self, record: &Record) {
+        match write_record(&mut self.file, record) {
+            Ok(_) => self.records += 1,
+            Err(err) => log::error!("spool: {:?}", err),
+        }
+    }
+
+    /// Rewrite the log with the live messages once most records are obsolete.
+    fn maybe_compact(&mut self) {
+        if self.records < COMPACT_RECORDS || self.records < self.live * 4 {
+            return;
+        }
+
+        match compact(&self.path) {
+            Ok((file, live)) => {
+                log::debug!("spool: compact {} records to {}", self.records, live.len());
+                self.file = file;
+                self.records = live.len();
+                self.live = live.len();
+            }
+            Err(err) => log::error!("spool: compact: {:?}", err),
+        }
+    }
+}
+
+/// Replace the log at `path` with one holding only its live messages, returns it opened for appending.
+fn compact(path: &Path) -> io::Result<(File, Vec<Entry>)> {
+    let mut live = Vec::new();
+    match File::open(path) {
+        Ok(file) => {
+            let mut reader = BufReader::new(file);
+            match read_header(&mut reader) {
+                Ok(()) => {
+                    while let Some(record) = read_record(&mut reader) {
+                        match record {
+                            Record::Add(entry) => live.push(*entry),
+                            Record::Remove(sequence) => {
+                                if let Some(i) =
+                                    live.iter().position(|entry| entry.sequence == sequence)
+                                {
+                                    live.remove(i);
+                                }
+                            }
+                        }
+                    }
+                }
+                Err(version) => {
+                    let aside = path.with_extension(format!("spool.{version}"));
+                    log::warn!("spool: unknown format, moved to {}", aside.display());
+                    fs::rename(path, aside)?;
+                }
+            }
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err),
+    }
+
+    let now = SystemTime::now();
+    live.retain(|entry| entry.expire > now);
+
+    // The new log replaces the old one at once
+    let tmp_path = path.with_extension("spool.tmp");
+    let mut writer = BufWriter::new(File::create(&tmp_path)?);
+    writer.write_all(MAGIC)?;
+    writer.write_all(&FORMAT.to_le_bytes())?;
+    for entry in &live {
+        write_record(&mut writer, &Record::Add(Box::new(entry.clone())))?;
+    }
+    writer.into_inner()?.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+
+    let file = OpenOptions::new().append(true).open(path)?;
+    Ok((file, live))
+}
+
+/// Check the magic and the format, returns the version found otherwise, 0 without magic.
+fn read_header(reader: &mut impl Read) -> Result<(), u32> {
+    let mut magic = [0; 8];
+    let mut version = [0; 4];
+    if reader.read_exact(&mut magic).is_err() || magic != *MAGIC {
+        return Err(0);
+    }
+    if reader.read_exact(&mut version).is_err() {
+        return Err(0);
+    }
+
+    match u32::from_le_bytes(version) {
+        FORMAT => Ok(()),
+        version => Err(version),
+    }
+}
+
+/// A record is its encoded length as u32 followed by the encoding, written at once.
+fn write_record(writer: &mut impl Write, record: &Record) -> io::Result<()> {
+    let data = encode(record)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+    if data.len() > MAX_RECORD_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("record of {} bytes", data.len()),
+        ));
+    }
+    let mut buf = Vec::with_capacity(4 + data.len());
+    buf.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    buf.extend_from_slice(&data);
+    writer.write_all(&buf)
+}
+
+fn read_record(reader: &mut impl Read) -> Option<Record> {
+    let mut len = [0; 4];
+    reader.read_exact(&mut len).ok()?;
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_RECORD_SIZE {
+        log::warn!("spool: record of {} bytes, drop the rest of the log", len);
+        return None;
+    }
+    let mut data = vec![0; len];
+    if reader.read_exact(&mut data).is_err() {
+        log::warn!("spool: drop torn record");
+        return None;
+    }
+
+    match decode::<Record>(&data) {
+        Ok(record) => Some(record),
+        Err(Error::Decode(err)) => {
+            log::warn!("spool: {}", err);
+            None
+        }
+        Err(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Spool;
+    use crate::{EndpointID, LabelOp, Message, Selector, Sequence};
+    use std::{
+        env, fs,
+        time::{Duration, Instant},
+    };
+
+    fn message(number: u64, sender: EndpointID) -> crate::EncodedMessage {
+        let mut selector = Selector::unicast(LabelOp::from("absent"));
+        selector.sequence = Some(Sequence {
+            sender,
+            stream: 0,
+            number,
+        });
+        Message::new(selector, number.to_string()).into_encoded()
+    }
+
+    #[test]
+    fn reload() {
+        let dir = env::temp_dir().join(format!("ipmb-spool-{}", uuid::Uuid::new_v4()));
+        let sender = EndpointID::new();
+        let now = Instant::now();
+
+        {
+            let (mut spool, messages) = Spool::open(&dir, "test").unwrap();
+            assert!(messages.is_empty());
+
+            spool.add(now + Duration::from_secs(60), &message(0, sender));
+            spool.add(now + Duration::from_secs(60), &message(1, sender));
+            spool.add(now, &message(2, sender));
+            spool.remove(Spool::key(&message(0, sender)));
+        }
+
+        let (_spool, messages) = Spool::open(&dir, "test").unwrap();
+        let numbers: Vec<_> = messages
+            .iter()
+            .map(|(expire, msg)| {
+                assert!(*expire > now);
+                msg.selector.sequence.unwrap().number
+            })
+            .collect();
+        assert_eq!(numbers, [1]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn compact() {
+        let dir = env::temp_dir().join(format!("ipmb-spool-{}", uuid::Uuid::new_v4()));
+        let sender = EndpointID::new();
+        let expire = Instant::now() + Duration::from_secs(60);
+
+        {
+            let (mut spool, _) = Spool::open(&dir, "test").unwrap();
+            spool.add(expire, &message(0, sender));
+            for number in 1..=super::COMPACT_RECORDS as u64 {
+                spool.add(expire, &message(number, sender));
+                spool.remove(Spool::key(&message(number, sender)));
+            }
+            assert!(spool.records < super::COMPACT_RECORDS);
+        }
+
+        let len = fs::metadata(dir.join("test.spool")).unwrap().len();
+        assert!(len < 4096, "{len}");
+        let (_spool, messages) = Spool::open(&dir, "test").unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].1.selector.sequence.unwrap().number, 0);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn corrupt_length() {
+        let dir = env::temp_dir().join(format!("ipmb-spool-{}", uuid::Uuid::new_v4()));
+        let sender = EndpointID::new();
+
+        {
+            let (mut spool, _) = Spool::open(&dir, "test").unwrap();
+            spool.add(
+                Instant::now() + Duration::from_secs(60),
+                &message(0, sender),
+            );
+        }
+        let mut log = fs::read(dir.join("test.spool")).unwrap();
+        log.extend_from_slice(&u32::MAX.to_le_bytes());
+        log.extend_from_slice(b"garbage");
+        fs::write(dir.join("test.spool"), log).unwrap();
+
+        let (_spool, messages) = Spool::open(&dir, "test").unwrap();
+        assert_eq!(messages.len(), 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn unknown_format() {
+        let dir = env::temp_dir().join(format!("ipmb-spool-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("test.spool"), b"ipmbspl\0\x09\0\0\0garbage").unwrap();
+
+        let (_spool, messages) = Spool::open(&dir, "test").unwrap();
+        assert!(messages.is_empty());
+        assert!(dir.join("test.spool.9").exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,245 @@
+use crate::Selector;
+use serde::{Deserialize, Serialize};
+use std::{
+    cell::RefCell,
+    fmt::{self, Display, Formatter, Write},
+};
+
+/// The W3C trace context of a message, see [`Selector::trace`].
+///
+/// Sends made while a context is entered on the thread, with [`TraceContext::enter`] or
+/// [`Message::enter_trace`](crate::Message::enter_trace), carry a child of it, so the message
+/// belongs to the same trace.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TraceContext {
+    pub trace_id: [u8; 16],
+    /// The span that sent the message, the parent of the span that receives it.
+    pub span_id: [u8; 8],
+    /// Trace flags, `0x01` is sampled.
+    pub flags: u8,
+    /// Key value pairs passed along the trace.
+    pub baggage: Vec<(String, String)>,
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<TraceContext>> = const { RefCell::new(None) };
+}
+
+impl TraceContext {
+    /// Start a new sampled trace.
+    pub fn new() -> Self {
+        Self {
+            trace_id: random_id(),
+            span_id: random_id(),
+            flags: 0x01,
+            baggage: vec![],
+        }
+    }
+
+    /// The context of a new span in the same trace.
+    pub fn child(&self) -> Self {
+        Self {
+            span_id: random_id(),
+            ..self.clone()
+        }
+    }
+
+    /// Parse a `traceparent` header, e.g. `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`.
+    pub fn from_traceparent(s: &str) -> Option<Self> {
+        let mut parts = s.trim().split('-');
+        let (version, trace_id, span_id, flags) =
+            (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+        if version != "00" || parts.next().is_some() {
+            return None;
+        }
+
+        let context = Self {
+            trace_id: parse_hex(trace_id)?,
+            span_id: parse_hex(span_id)?,
+            flags: parse_hex::<1>(flags)?[0],
+            baggage: vec![],
+        };
+        (context.trace_id != [0; 16] && context.span_id != [0; 8]).then_some(context)
+    }
+
+    /// The context entered on this thread.
+    pub fn current() -> Option<Self> {
+        CURRENT.with(|current| current.borrow().clone())
+    }
+
+    /// Make this the context of the thread until the guard is dropped.
+    pub fn enter(self) -> TraceGuard {
+        TraceGuard {
+            previous: CURRENT.with(|current| current.replace(Some(self))),
+            #[cfg(feature = "tracing")]
+            _span: None,
+        }
+    }
+}
+
+impl Default for TraceContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Formats the `traceparent` header.
+impl Display for TraceContext {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "00-{}-{}-{:02x}",
+            hex(&self.trace_id),
+            hex(&self.span_id),
+            self.flags
+        )
+    }
+}
+
+/// Restores the context the thread had before, see [`TraceContext::enter`].
+pub struct TraceGuard {
+    previous: Option<TraceContext>,
+    #[cfg(feature = "tracing")]
+    _span: Option<tracing::span::EnteredSpan>,
+}
+
+impl Drop for TraceGuard {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        CURRENT.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+/// Continue the trace of a received message on this thread.
+pub(crate) fn enter_received(selector: &Selector) -> Option<TraceGuard> {
+    let parent = selector.trace.as_ref()?;
+    let context = parent.child();
+
+    #[cfg(feature = "tracing")]
+    let span = tracing::info_span!(
+        "ipmb.recv",
+        trace_id = %hex(&context.trace_id),
+        span_id = %hex(&context.span_id),
+        parent_id = %hex(&parent.span_id),
+        label_op = %selector.label_op,
+    );
+
+    #[allow(unused_mut)]
+    let mut guard = context.enter();
+    #[cfg(feature = "tracing")]
+    {
+        guard._span = Some(span.entered());
+    }
+    Some(guard)
+}
+
+/// Give a message sent without a context a child of the one entered on the thread, or with the
+/// `tracing` feature, a new trace.
+pub(crate) fn inject(selector: &mut Selector) {
+    if selector.trace.is_some() {
+        return;
+    }
+
+    selector.trace = match TraceContext::current() {
+        Some(current) => Some(current.child()),
+        None if cfg!(feature = "tracing") => Some(TraceContext::new()),
+        None => None,
+    };
+}
+
+/// The span of sending a message, whose context was injected.
+#[cfg(feature = "tracing")]
+pub(crate) fn send_span(selector: &Selector) -> tracing::Span {
+    let Some(context) = &selector.trace else {
+        return tracing::Span::none();
+    };
+    let parent = TraceContext::current().map(|current| hex(&current.span_id));
+
+    tracing::info_span!(
+        "ipmb.send",
+        trace_id = %hex(&context.trace_id),
+        span_id = %hex(&context.span_id),
+        parent_id = parent,
+        label_op = %selector.label_op,
+    )
+}
+
+fn random_id<const N: usize>() -> [u8; N] {
+    loop {
+        let id: [u8; N] = std::array::from_fn(|_| rand::random());
+        // All zeros is invalid
+        if id != [0; N] {
+            return id;
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut s, b| {
+        let _ = write!(s, "{b:02x}");
+        s
+    })
+}
+
+fn parse_hex<const N: usize>(s: &str) -> Option<[u8; N]> {
+    if s.len() != N * 2
+        || !s
+            .bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+    {
+        return None;
+    }
+
+    let mut bytes = [0; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::TraceContext;
+
+    #[test]
+    fn traceparent() {
+        let s = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let context = TraceContext::from_traceparent(s).unwrap();
+        assert_eq!(context.to_string(), s);
+
+        let child = context.child();
+        assert_eq!(child.trace_id, context.trace_id);
+        assert_ne!(child.span_id, context.span_id);
+
+        assert!(TraceContext::from_traceparent(
+            "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+        )
+        .is_none());
+        assert!(TraceContext::from_traceparent(
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01"
+        )
+        .is_none());
+        assert!(TraceContext::from_traceparent(
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01"
+        )
+        .is_none());
+        assert!(
+            TraceContext::from_traceparent("00-4bf92f3577b34da6-00f067aa0ba902b7-01").is_none()
+        );
+    }
+
+    #[test]
+    fn enter() {
+        assert_eq!(TraceContext::current(), None);
+        let outer = TraceContext::new();
+        {
+            let _outer = outer.clone().enter();
+            {
+                let _inner = outer.child().enter();
+                assert_ne!(TraceContext::current(), Some(outer.clone()));
+            }
+            assert_eq!(TraceContext::current(), Some(outer));
+        }
+        assert_eq!(TraceContext::current(), None);
+    }
+}
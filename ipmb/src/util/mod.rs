@@ -1,6 +1,9 @@
 use rand::Rng;
 use serde::{Deserialize, Serialize};
-use std::ops::{Bound, RangeBounds};
+use std::{
+    fmt,
+    ops::{Bound, RangeBounds},
+};
 use type_uuid::Bytes;
 
 #[allow(dead_code)]
@@ -40,13 +43,34 @@ impl Align4 for u32 {
     }
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+/// Unique id assigned to an endpoint by the bus controller, a new one is assigned on rejoin.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub struct EndpointID(Bytes);
 
 impl EndpointID {
-    pub fn new() -> Self {
+    pub(crate) fn new() -> Self {
         Self(uuid::Uuid::new_v4().into_bytes())
     }
+
+    pub fn from_bytes(bytes: Bytes) -> Self {
+        Self(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &Bytes {
+        &self.0
+    }
+}
+
+impl fmt::Debug for EndpointID {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "EndpointID({})", self)
+    }
+}
+
+impl fmt::Display for EndpointID {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&uuid::Uuid::from_bytes(self.0), f)
+    }
 }
 
 #[allow(dead_code)]